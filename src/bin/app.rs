@@ -1,6 +1,6 @@
 #[tokio::main]
 pub async fn main() {
-    let mut mikit = mikit_rust::kit::MiKit::default();
+    let mikit = mikit_rust::kit::MiKit::new("mikit", "com.nickming").unwrap();
     let devices: Vec<String> = mikit
         .fetch_devices()
         .await
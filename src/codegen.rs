@@ -0,0 +1,513 @@
+//! 根据MIoT spec生成设备的强类型封装，一般在`build.rs`中使用：
+//!
+//! ```ignore
+//! let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("devices.rs");
+//! mikit_rust::codegen::generate_dir("specs", &out).unwrap();
+//! ```
+//!
+//! 然后在代码中`include!(concat!(env!("OUT_DIR"), "/devices.rs"));`，
+//! 生成的代码依赖`anyhow`。
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Ok;
+
+use crate::spec::{DeviceSpec, PropertyFormat, SpecAction, SpecProperty, SpecService};
+
+static KEYWORDS: [&str; 51] = [
+    "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn", "for",
+    "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+    "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where",
+    "while", "async", "await", "dyn", "abstract", "become", "box", "do", "final", "macro",
+    "override", "priv", "typeof", "unsized", "virtual", "yield", "try",
+];
+
+/// 读取目录下所有`{model}.json`格式的spec文件，生成的代码写入`out`
+pub fn generate_dir(spec_dir: impl AsRef<Path>, out: impl AsRef<Path>) -> anyhow::Result<()> {
+    let mut paths: Vec<_> = fs::read_dir(spec_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    let mut code = String::new();
+    for path in paths {
+        let model = path.file_stem().unwrap_or_default().to_string_lossy();
+        let spec = DeviceSpec::parse(&model, &fs::read_to_string(&path)?)?;
+        code.push_str(&generate(&spec));
+    }
+    fs::write(out, code)?;
+    Ok(())
+}
+
+/// 为一个型号生成模块，包含设备结构体和属性可选值对应的枚举
+pub fn generate(spec: &DeviceSpec) -> String {
+    let names = Names::new(spec);
+    let struct_name = pascal_case(&spec.model, "Device");
+    let mut code = String::new();
+    writeln!(code, "/// {} ({})", doc_line(&spec.description), spec.model).unwrap();
+    code.push_str("#[allow(dead_code, unused_imports)]\n");
+    writeln!(code, "pub mod {} {{", snake_case(&spec.model, "device")).unwrap();
+    code.push_str(
+        "    use ::mikit_rust::kit::{MiKit, PROPERTY_FAILED_CODE};\n    use ::mikit_rust::models::{ActionResult, MikitError, MiotValue};\n\n",
+    );
+    for service in spec.services.iter() {
+        for property in service.properties.iter() {
+            if !property.value_list.is_empty() {
+                write_enum(&mut code, &names.enum_name(service, property), property);
+            }
+        }
+    }
+
+    writeln!(code, "    pub struct {}<'a> {{", struct_name).unwrap();
+    code.push_str("        kit: &'a MiKit,\n        did: String,\n    }\n\n");
+    writeln!(code, "    impl<'a> {}<'a> {{", struct_name).unwrap();
+    writeln!(
+        code,
+        "        pub const MODEL: &'static str = {:?};\n",
+        spec.model
+    )
+    .unwrap();
+    code.push_str(
+        "        pub fn new(kit: &'a MiKit, did: &str) -> Self {\n            Self {\n                kit,\n                did: did.to_string(),\n            }\n        }\n",
+    );
+    for service in spec.services.iter() {
+        for property in service.properties.iter() {
+            write_accessors(&mut code, &names, service, property);
+        }
+        for action in service.actions.iter() {
+            write_action(&mut code, &names, service, action);
+        }
+    }
+    code.push_str("    }\n}\n");
+    code
+}
+
+fn write_enum(code: &mut String, name: &str, property: &SpecProperty) {
+    let mut variants: Vec<(String, i64)> = vec![];
+    for item in property.value_list.iter() {
+        let mut variant = pascal_case(&item.description, "Value");
+        if variant.is_empty() || variants.iter().any(|(name, _)| *name == variant) {
+            variant = format!("Value{}", item.value).replace('-', "Minus");
+        }
+        variants.push((variant, item.value));
+    }
+    writeln!(code, "    /// {}", doc_line(&property.description)).unwrap();
+    code.push_str("    #[derive(Clone, Copy, Debug, PartialEq, Eq)]\n");
+    writeln!(code, "    pub enum {} {{", name).unwrap();
+    for (variant, _) in variants.iter() {
+        writeln!(code, "        {},", variant).unwrap();
+    }
+    code.push_str("    }\n\n");
+    writeln!(code, "    impl {} {{", name).unwrap();
+    code.push_str("        pub fn value(self) -> i64 {\n            match self {\n");
+    for (variant, value) in variants.iter() {
+        writeln!(code, "                {}::{} => {},", name, variant, value).unwrap();
+    }
+    code.push_str("            }\n        }\n\n");
+    code.push_str(
+        "        pub fn from_value(value: i64) -> Option<Self> {\n            match value {\n",
+    );
+    for (variant, value) in variants.iter() {
+        writeln!(
+            code,
+            "                {} => Some({}::{}),",
+            value, name, variant
+        )
+        .unwrap();
+    }
+    code.push_str("                _ => None,\n            }\n        }\n    }\n\n");
+}
+
+fn write_accessors(
+    code: &mut String,
+    names: &Names,
+    service: &SpecService,
+    property: &SpecProperty,
+) {
+    let method = names.property_name(service, property);
+    let full_name = format!("{}.{}", service.name(), property.name());
+    let rust_type = names.rust_type(service, property);
+    let doc = property_doc(property);
+    if property.readable() {
+        writeln!(code, "\n        /// {}", doc).unwrap();
+        writeln!(
+            code,
+            "        pub async fn get_{}(&self) -> ::anyhow::Result<{}> {{",
+            method, rust_type
+        )
+        .unwrap();
+        writeln!(
+            code,
+            "            let value = self\n                .kit\n                .get_property_by_iid(&self.did, {}, {})\n                .await?;",
+            service.iid, property.iid
+        )
+        .unwrap();
+        match getter_convert(&rust_type, property) {
+            Some(convert) => writeln!(
+                code,
+                "            value{}\n                .ok_or_else(|| MikitError::Property({:?}.to_string(), PROPERTY_FAILED_CODE).into())",
+                convert, full_name
+            )
+            .unwrap(),
+            None => code.push_str("            Ok(value)\n"),
+        }
+        code.push_str("        }\n");
+    }
+    if property.writable() {
+        let (param_type, json) = setter_param(&rust_type, property, "value");
+        writeln!(code, "\n        /// {}", doc).unwrap();
+        writeln!(
+            code,
+            "        pub async fn set_{}(&self, value: {}) -> ::anyhow::Result<()> {{",
+            method, param_type
+        )
+        .unwrap();
+        writeln!(
+            code,
+            "            self.kit\n                .set_property_by_iid(&self.did, {}, {}, {})\n                .await",
+            service.iid, property.iid, json
+        )
+        .unwrap();
+        code.push_str("        }\n");
+    }
+}
+
+fn write_action(code: &mut String, names: &Names, service: &SpecService, action: &SpecAction) {
+    let mut params: Vec<(String, String, String)> = vec![];
+    for piid in action.in_args.iter() {
+        let Some(property) = service.properties.iter().find(|item| item.iid == *piid) else {
+            continue;
+        };
+        let mut name = snake_case(property.name(), "arg");
+        if params.iter().any(|(param, _, _)| *param == name) {
+            name = format!("{}_{}", name, piid);
+        }
+        let rust_type = names.rust_type(service, property);
+        let (param_type, json) = setter_param(&rust_type, property, &name);
+        params.push((name, param_type, json));
+    }
+    let signature: String = params
+        .iter()
+        .map(|(name, param_type, _)| format!(", {}: {}", name, param_type))
+        .collect();
+    let args: Vec<String> = params.iter().map(|(_, _, json)| json.clone()).collect();
+    writeln!(code, "\n        /// {}", doc_line(&action.description)).unwrap();
+    writeln!(
+        code,
+        "        pub async fn {}(&self{}) -> ::anyhow::Result<ActionResult> {{",
+        names.action_name(service, action),
+        signature
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "            self.kit\n                .call_action(&self.did, {}, {}, &[{}])\n                .await",
+        service.iid,
+        action.iid,
+        args.join(", ")
+    )
+    .unwrap();
+    code.push_str("        }\n");
+}
+
+/// 读取时从`MiotValue`转换为属性类型的表达式，返回`None`时直接返回`MiotValue`
+fn getter_convert(rust_type: &str, property: &SpecProperty) -> Option<String> {
+    if !property.value_list.is_empty() {
+        return Some(format!(
+            "\n                .as_i64()\n                .and_then({}::from_value)",
+            rust_type
+        ));
+    }
+    let convert = match property.format {
+        PropertyFormat::Bool => "\n                .as_bool()".to_string(),
+        PropertyFormat::Int64 => "\n                .as_i64()".to_string(),
+        PropertyFormat::Uint8
+        | PropertyFormat::Uint16
+        | PropertyFormat::Uint32
+        | PropertyFormat::Int8
+        | PropertyFormat::Int16
+        | PropertyFormat::Int32 => format!(
+            "\n                .as_i64()\n                .and_then(|value| {}::try_from(value).ok())",
+            rust_type
+        ),
+        PropertyFormat::Float => "\n                .as_f64()".to_string(),
+        PropertyFormat::String | PropertyFormat::Hex => {
+            "\n                .as_str()\n                .map(str::to_string)".to_string()
+        }
+        PropertyFormat::Unknown => return None,
+    };
+    Some(convert)
+}
+
+/// 写入时的参数类型和转换为`MiotValue`的表达式
+fn setter_param(rust_type: &str, property: &SpecProperty, arg: &str) -> (String, String) {
+    if !property.value_list.is_empty() {
+        return (
+            rust_type.to_string(),
+            format!("MiotValue::from({}.value())", arg),
+        );
+    }
+    match property.format {
+        PropertyFormat::String | PropertyFormat::Hex => {
+            ("&str".to_string(), format!("MiotValue::from({})", arg))
+        }
+        PropertyFormat::Unknown => ("MiotValue".to_string(), arg.to_string()),
+        _ => (rust_type.to_string(), format!("MiotValue::from({})", arg)),
+    }
+}
+
+/// 描述中可能带有换行，合并为一行后才能放在`///`注释中
+fn doc_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn property_doc(property: &SpecProperty) -> String {
+    let mut doc = doc_line(&property.description);
+    if let Some(range) = property.value_range {
+        write!(doc, " [{}, {}], step {}", range.min, range.max, range.step).unwrap();
+    }
+    if let Some(unit) = property
+        .unit
+        .as_ref()
+        .filter(|unit| unit.as_str() != "none")
+    {
+        write!(doc, " ({})", unit).unwrap();
+    }
+    doc
+}
+
+/// spec中的名称可能在不同服务中重复，重复时加上服务名作为前缀，
+/// 加上前缀后仍与其他名称相同时再加上序号
+struct Names {
+    properties: HashMap<(usize, usize), String>,
+    enums: HashMap<(usize, usize), String>,
+    actions: HashMap<(usize, usize), String>,
+}
+
+impl Names {
+    fn new(spec: &DeviceSpec) -> Self {
+        let mut property_counts = HashMap::new();
+        let mut action_counts = HashMap::new();
+        for service in spec.services.iter() {
+            for property in service.properties.iter() {
+                *property_counts
+                    .entry(property.name().to_string())
+                    .or_insert(0) += 1;
+            }
+            for action in service.actions.iter() {
+                *action_counts.entry(action.name().to_string()).or_insert(0) += 1;
+            }
+        }
+        let mut names = Self {
+            properties: HashMap::new(),
+            enums: HashMap::new(),
+            actions: HashMap::new(),
+        };
+        // 结构体已有的方法名和模块中引入的类型名
+        let mut methods = HashSet::from(["new".to_string()]);
+        let mut types: HashSet<String> = ["MiKit", "ActionResult", "MikitError", "MiotValue"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        types.insert(pascal_case(&spec.model, "Device"));
+        for service in spec.services.iter() {
+            for property in service.properties.iter() {
+                let name = qualified(&property_counts, service.name(), property.name());
+                // 属性方法带有`get_`和`set_`前缀，不会与关键字冲突
+                let method = unique(words(&name).join("_").to_lowercase(), "_", |method| {
+                    methods.contains(&format!("get_{}", method))
+                        || methods.contains(&format!("set_{}", method))
+                });
+                methods.insert(format!("get_{}", method));
+                methods.insert(format!("set_{}", method));
+                names.properties.insert((service.iid, property.iid), method);
+                if !property.value_list.is_empty() {
+                    let enum_name = unique(pascal_case(&name, "Property"), "", |enum_name| {
+                        types.contains(enum_name)
+                    });
+                    types.insert(enum_name.clone());
+                    names.enums.insert((service.iid, property.iid), enum_name);
+                }
+            }
+        }
+        for service in spec.services.iter() {
+            for action in service.actions.iter() {
+                let name = qualified(&action_counts, service.name(), action.name());
+                let mut method = snake_case(&name, "action");
+                if methods.contains(&method) {
+                    method = format!("{}_action", method);
+                }
+                let method = unique(method, "_", |method| methods.contains(method));
+                methods.insert(method.clone());
+                names.actions.insert((service.iid, action.iid), method);
+            }
+        }
+        names
+    }
+
+    fn property_name(&self, service: &SpecService, property: &SpecProperty) -> String {
+        self.properties[&(service.iid, property.iid)].clone()
+    }
+
+    fn enum_name(&self, service: &SpecService, property: &SpecProperty) -> String {
+        self.enums[&(service.iid, property.iid)].clone()
+    }
+
+    fn action_name(&self, service: &SpecService, action: &SpecAction) -> String {
+        self.actions[&(service.iid, action.iid)].clone()
+    }
+
+    fn rust_type(&self, service: &SpecService, property: &SpecProperty) -> String {
+        if !property.value_list.is_empty() {
+            return self.enum_name(service, property);
+        }
+        match property.format {
+            PropertyFormat::Bool => "bool",
+            PropertyFormat::Uint8 => "u8",
+            PropertyFormat::Uint16 => "u16",
+            PropertyFormat::Uint32 => "u32",
+            PropertyFormat::Int8 => "i8",
+            PropertyFormat::Int16 => "i16",
+            PropertyFormat::Int32 => "i32",
+            PropertyFormat::Int64 => "i64",
+            PropertyFormat::Float => "f64",
+            PropertyFormat::String | PropertyFormat::Hex => "String",
+            PropertyFormat::Unknown => "MiotValue",
+        }
+        .to_string()
+    }
+}
+
+/// 名称重复时加上服务名作为前缀
+fn qualified(counts: &HashMap<String, usize>, service: &str, name: &str) -> String {
+    if counts.get(name).copied().unwrap_or(0) > 1 {
+        format!("{}-{}", service, name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// `taken`的名称加上从2开始的序号，直到不再重复
+fn unique(name: String, separator: &str, taken: impl Fn(&str) -> bool) -> String {
+    if !taken(&name) {
+        return name;
+    }
+    (2..)
+        .map(|idx| format!("{}{}{}", name, separator, idx))
+        .find(|name| !taken(name))
+        .unwrap()
+}
+
+fn words(name: &str) -> Vec<String> {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_string())
+        .collect()
+}
+
+/// 转换为snake_case标识符，以数字开头时加上前缀，关键字加上`_`后缀
+fn snake_case(name: &str, prefix: &str) -> String {
+    let ident = words(name).join("_").to_lowercase();
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        return format!("{}_{}", prefix, ident)
+            .trim_end_matches('_')
+            .to_string();
+    }
+    if KEYWORDS.contains(&ident.as_str()) {
+        return format!("{}_", ident);
+    }
+    ident
+}
+
+/// 转换为PascalCase标识符，以数字开头时加上前缀
+fn pascal_case(name: &str, prefix: &str) -> String {
+    let ident: String = words(name)
+        .iter()
+        .map(|word| {
+            let mut chars = word.chars();
+            let first = chars.next().unwrap_or_default().to_ascii_uppercase();
+            format!("{}{}", first, chars.as_str().to_lowercase())
+        })
+        .collect();
+    if ident.starts_with(|c: char| c.is_ascii_digit()) {
+        return format!("{}{}", prefix, ident);
+    }
+    if ident == "Self" {
+        return format!("{}{}", prefix, ident);
+    }
+    ident
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use super::{generate, generate_dir, pascal_case, snake_case};
+    use crate::spec::test::LIGHT_SPEC;
+    use crate::spec::DeviceSpec;
+    use crate::utils::get_random_string;
+
+    #[test]
+    fn test_identifiers() {
+        assert_eq!(
+            "zhimi_airpurifier_ma4",
+            snake_case("zhimi.airpurifier.ma4", "device")
+        );
+        assert_eq!(
+            "ZhimiAirpurifierMa4",
+            pascal_case("zhimi.airpurifier.ma4", "Device")
+        );
+        assert_eq!(
+            "device_090615_switch",
+            snake_case("090615.switch", "device")
+        );
+        assert_eq!("Device090615Switch", pascal_case("090615.switch", "Device"));
+        assert_eq!("type_", snake_case("type", "property"));
+        assert_eq!(
+            "ColorTemperature",
+            pascal_case("color-temperature", "Property")
+        );
+        assert_eq!("", pascal_case("睡眠", "Value"));
+        assert_eq!("Value1Hour", pascal_case("1 hour", "Value"));
+    }
+
+    #[test]
+    fn test_generate() {
+        let spec = DeviceSpec::parse("yeelink.light.color1", LIGHT_SPEC).unwrap();
+        let code = generate(&spec);
+        assert!(code.contains("pub mod yeelink_light_color1 {"));
+        assert!(code.contains("pub struct YeelinkLightColor1<'a>"));
+        assert!(code.contains("pub const MODEL: &'static str = \"yeelink.light.color1\";"));
+        assert!(code.contains("pub async fn get_on(&self) -> ::anyhow::Result<bool>"));
+        assert!(code.contains("pub async fn get_mode(&self) -> ::anyhow::Result<Mode>"));
+        assert!(code.contains("pub async fn set_brightness(&self, value: u8)"));
+        assert!(code.contains(".and_then(|value| u8::try_from(value).ok())"));
+        assert!(!code.contains("serde_json"));
+        assert!(code.contains("/// Brightness [1, 100], step 1 (percentage)"));
+        assert!(code.contains("pub enum Mode {\n        Day,\n        Night,\n    }"));
+        assert!(code.contains("pub async fn set_mode(&self, value: Mode)"));
+        assert!(code.contains("pub async fn get_color_temperature(&self) -> ::anyhow::Result<u32>"));
+        assert!(!code.contains("set_color_temperature"));
+        assert!(code.contains("pub async fn toggle(&self) -> ::anyhow::Result<ActionResult>"));
+        assert!(code.contains(".call_action(&self.did, 2, 1, &[])"));
+    }
+
+    #[test]
+    fn test_generate_dir() {
+        // tests/codegen.rs会编译这份生成结果，spec变化后需要重新生成
+        let out = env::temp_dir().join(format!("mikit_devices_{}.rs", get_random_string(8)));
+        generate_dir("tests/fixtures/specs", &out).unwrap();
+        let code = fs::read_to_string(&out).unwrap();
+        fs::remove_file(&out).unwrap();
+        assert_eq!(include_str!("../tests/fixtures/devices.rs"), code);
+        assert!(code.contains("/// Mode with a second line\n"));
+        assert!(code.contains("pub async fn new_action(&self)"));
+        assert!(code.contains("pub async fn get_type_action(&self, on: bool)"));
+        assert!(code.contains("pub async fn get_fan_on(&self) -> ::anyhow::Result<bool>"));
+        assert!(code.contains("pub async fn get_fan_on_2(&self) -> ::anyhow::Result<u8>"));
+        assert!(code.contains("pub async fn fan_set(&self, mode: Mode, type_: &str, mode_2: Mode"));
+    }
+}
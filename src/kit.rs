@@ -1,128 +1,2306 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 use std::sync::{atomic::AtomicBool, Arc, RwLock};
+use std::time::Duration;
 
 use anyhow::Ok;
+use futures::{stream, StreamExt};
+use log::{trace, warn};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
+use tokio::sync::{Mutex, OnceCell};
 
 use crate::models::{
-    CommandResponse, Device, DeviceListResult, DeviceProperties, DevicePropertiesRequestParams,
-    MikitError,
+    ActionRequestParams, ActionResult, CaptchaChallenge, CommandResponse, ConsumableItem,
+    ConsumableListResult, ConsumableRequestParams, ConsumableThreshold, Device, DeviceAction,
+    DeviceConsumables, DeviceDataRequestParams, DeviceListResult, DeviceProperties,
+    DevicePropertiesRequestParams, Granularity, HistoryKind, HistoryRecord, Home, HomeListResult,
+    LoginOutcome, MikitError, MiotValue, ModelInfo, PropertyValue, ProtocolMode, QrLoginTicket,
+    RawConsumable, Region, Room, RoomDevices, RpcRequestParams, Scene, SceneListResult,
+    SessionBundle, StatPoint, StatisticsRequestParams, StoreKey, VerificationChallenge,
+    VerificationMethod,
 };
-use crate::network::CommandReqeust;
-use crate::{models::MiAccount, network::HttpClient, store::DataSore};
+use crate::network::{CommandReqeust, HttpOptions};
+use crate::session::{decode_session, encode_session, SESSION_VERSION};
+use crate::spec::{
+    read_local_spec, spec_not_found, DeviceSpec, PropertyValidationError, SpecInstance,
+    SpecOptions, SpecProperty, SpecSource, ValidationIssue,
+};
+use crate::store::{abort, DataSore};
+use crate::{models::MiAccount, network::HttpClient};
+
+static DEFAULT_PROFILE: &str = "default";
+/// 单次请求历史数据的最大条数
+static HISTORY_PAGE_SIZE: usize = 1000;
+/// 单次读取属性的最大数量，超出时拆分为多个请求
+static PROPERTIES_BATCH_SIZE: usize = 100;
+/// 同时进行的属性读取请求数
+static PROPERTIES_CONCURRENCY: usize = 4;
+/// 所在批次请求失败或云端未返回结果的属性使用的错误码
+pub static PROPERTY_FAILED_CODE: i64 = -1;
+static PROFILES_KEY: &str = "profiles";
+static ACTIVE_PROFILE_KEY: &str = "active_profile";
+/// 旧版本直接保存在根命名空间下的key，首次启动时迁移到默认账号
+static LEGACY_KEYS: [&str; 2] = ["account", "region"];
 
 pub struct MiKit {
     http_client: Arc<HttpClient>,
+    store: Arc<DataSore>,
     db: Arc<DataSore>,
+    profile: String,
     account: Arc<RwLock<Option<MiAccount>>>,
     is_logged: AtomicBool,
+    /// 型号没有spec、跳过了写入校验的设备，避免每次写入都重新请求
+    unvalidated_devices: RwLock<HashSet<String>>,
+    spec_options: Arc<SpecOptions>,
+    /// spec服务器上已发布的型号列表，各账号共享，只请求一次
+    spec_instances: Arc<OnceCell<Vec<SpecInstance>>>,
+    /// 同一时间只续期一次，并发的请求等待续期结果
+    refresh_lock: Mutex<()>,
 }
 
-impl Default for MiKit {
-    fn default() -> Self {
-        MiKit::new("mikit", "com.nickming")
+impl MiKit {
+    /// 设置了`MIKIT_KEY_FILE`或`MIKIT_PASSPHRASE`环境变量时会开启加密存储，
+    /// 密钥错误时返回`MikitError::InvalidStoreKey`，有加密数据但没有设置密钥时返回
+    /// `MikitError::StoreKeyRequired`
+    pub fn new(application_name: &str, organization_name: &str) -> anyhow::Result<Self> {
+        MiKit::with_store_key(application_name, organization_name, StoreKey::from_env())
     }
-}
 
-impl MiKit {
-    pub fn new(application_name: &str, organization_name: &str) -> Self {
-        let db = DataSore::new(application_name, organization_name).unwrap();
-        let account = db.get::<MiAccount>("account").ok();
+    /// 使用加密存储保存账号凭据，已有的明文账号数据会在读取时迁移为密文
+    pub fn with_store_key(
+        application_name: &str,
+        organization_name: &str,
+        store_key: Option<StoreKey>,
+    ) -> anyhow::Result<Self> {
+        let mut builder = MiKitBuilder::new().application(application_name, organization_name);
+        if let Some(store_key) = store_key {
+            builder = builder.store_key(store_key);
+        }
+        builder.build()
+    }
+
+    pub fn builder() -> MiKitBuilder {
+        MiKitBuilder::new()
+    }
+
+    fn open_profile(
+        store: Arc<DataSore>,
+        profile: &str,
+        http_client: HttpClient,
+        spec_options: Arc<SpecOptions>,
+        spec_instances: Arc<OnceCell<Vec<SpecInstance>>>,
+    ) -> anyhow::Result<Self> {
+        register_profile(&store, profile)?;
+        let db = store.scoped(profile)?;
+        let account = match db.get_secret::<MiAccount>("account") {
+            Err(e) if is_store_key_error(&e) => return Err(e),
+            account => account.ok(),
+        };
         let is_logged = AtomicBool::new(account.is_some());
-        MiKit {
-            http_client: Arc::new(HttpClient::default()),
+        http_client.set_region(db.get::<Region>("region").unwrap_or_default());
+        Ok(MiKit {
+            http_client: Arc::new(http_client),
+            store,
             db: Arc::new(db),
+            profile: profile.to_string(),
             account: Arc::new(RwLock::new(account)),
             is_logged,
+            unvalidated_devices: RwLock::new(HashSet::new()),
+            spec_options,
+            spec_instances,
+            refresh_lock: Mutex::new(()),
+        })
+    }
+
+    /// 当前使用的账号名称
+    pub fn profile_name(&self) -> &str {
+        &self.profile
+    }
+
+    pub fn profiles(&self) -> anyhow::Result<Vec<String>> {
+        Ok(self
+            .store
+            .get::<Vec<String>>(PROFILES_KEY)
+            .unwrap_or_default())
+    }
+
+    /// 新增一个账号，每个账号拥有独立的登录态、区域和设备缓存
+    pub fn add_profile(&self, name: &str) -> anyhow::Result<()> {
+        if name.is_empty() {
+            return Err(MikitError::Profile("profile name is empty".to_string()).into());
+        }
+        register_profile(&self.store, name)
+    }
+
+    /// 切换当前使用的账号，下次启动时也会使用该账号
+    pub fn switch_profile(&mut self, name: &str) -> anyhow::Result<()> {
+        if !self.profiles()?.iter().any(|profile| profile == name) {
+            return Err(MikitError::Profile(format!("profile {} not found", name)).into());
+        }
+        *self = MiKit::open_profile(
+            self.store.clone(),
+            name,
+            self.http_client.fork(),
+            self.spec_options.clone(),
+            self.spec_instances.clone(),
+        )?;
+        self.store.set(ACTIVE_PROFILE_KEY, &name.to_string())
+    }
+
+    /// 删除账号及其所有数据，不能删除当前正在使用的账号，也不能删除下次启动时使用的账号
+    pub fn remove_profile(&self, name: &str) -> anyhow::Result<()> {
+        if name == self.profile {
+            return Err(MikitError::Profile("can not remove current profile".to_string()).into());
+        }
+        self.store.transaction(|tx| {
+            if tx.get::<String>(ACTIVE_PROFILE_KEY)?.as_deref() == Some(name) {
+                return Err(abort(MikitError::Profile(
+                    "can not remove active profile".to_string(),
+                )));
+            }
+            let mut profiles = tx.get::<Vec<String>>(PROFILES_KEY)?.unwrap_or_default();
+            profiles.retain(|profile| profile != name);
+            tx.set(PROFILES_KEY, &profiles)
+        })?;
+        self.store.drop_scope(name)?;
+        Ok(())
+    }
+
+    /// 获取另一个账号的独立实例，可以和当前实例在同一进程中并发使用
+    pub fn profile(&self, name: &str) -> anyhow::Result<MiKit> {
+        if !self.profiles()?.iter().any(|profile| profile == name) {
+            return Err(MikitError::Profile(format!("profile {} not found", name)).into());
         }
+        MiKit::open_profile(
+            self.store.clone(),
+            name,
+            self.http_client.fork(),
+            self.spec_options.clone(),
+            self.spec_instances.clone(),
+        )
     }
 
-    pub async fn login(&self, username: &str, password: &str) -> anyhow::Result<()> {
+    /// 账号密码登录，开启了二次验证的账号会返回`LoginOutcome::VerificationRequired`，
+    /// 此时需要调用`send_verification_ticket`和`verify_login`完成登录；
+    /// 返回`LoginOutcome::CaptchaRequired`时需要调用`login_with_captcha`
+    pub async fn login(&self, username: &str, password: &str) -> anyhow::Result<LoginOutcome> {
         let client = self.http_client.clone();
-        let account = client.login(username, password).await?;
+        let outcome = client.login(username, password).await?;
+        if let LoginOutcome::Success(account) = &outcome {
+            self.save_account(account)?;
+        }
+        Ok(outcome)
+    }
 
-        let db = self.db.clone();
-        db.set("account", &account)?;
+    /// 登录需要图形验证码时，带上用户识别出的验证码继续登录
+    pub async fn login_with_captcha(
+        &self,
+        username: &str,
+        password: &str,
+        challenge: &CaptchaChallenge,
+        code: &str,
+    ) -> anyhow::Result<LoginOutcome> {
+        let client = self.http_client.clone();
+        let outcome = client
+            .login_with_captcha(username, password, challenge, code)
+            .await?;
+        if let LoginOutcome::Success(account) = &outcome {
+            self.save_account(account)?;
+        }
+        Ok(outcome)
+    }
 
-        let mut guard = self.account.write().unwrap();
-        *guard = Some(account.clone());
+    /// 申请扫码登录二维码，将`qr_image_url`或`fetch_qr_image`的图片展示给用户后调用`wait_qr_login`
+    pub async fn start_qr_login(&self) -> anyhow::Result<QrLoginTicket> {
+        let client = self.http_client.clone();
+        client.fetch_qr_login().await
+    }
 
-        self.is_logged.store(true, Ordering::Relaxed);
+    pub async fn fetch_qr_image(&self, ticket: &QrLoginTicket) -> anyhow::Result<Vec<u8>> {
+        let client = self.http_client.clone();
+        client.fetch_qr_image(ticket).await
+    }
 
-        Ok(())
+    /// 等待米家APP扫码确认，成功后保存账号
+    pub async fn wait_qr_login(&self, ticket: &QrLoginTicket) -> anyhow::Result<MiAccount> {
+        let client = self.http_client.clone();
+        let account = client.wait_qr_login(ticket).await?;
+        self.save_account(&account)?;
+        Ok(account)
+    }
+
+    pub async fn send_verification_ticket(
+        &self,
+        challenge: &VerificationChallenge,
+        method: VerificationMethod,
+    ) -> anyhow::Result<()> {
+        let client = self.http_client.clone();
+        client.send_verification_ticket(challenge, method).await
+    }
+
+    pub async fn verify_login(
+        &self,
+        challenge: &VerificationChallenge,
+        method: VerificationMethod,
+        ticket: &str,
+    ) -> anyhow::Result<MiAccount> {
+        let client = self.http_client.clone();
+        let account = client.verify_login(challenge, method, ticket).await?;
+        self.save_account(&account)?;
+        Ok(account)
     }
 
     pub async fn fetch_devices(&self) -> anyhow::Result<Vec<Device>> {
+        let devices = self
+            .execute_command::<CommandResponse<DeviceListResult>>(CommandReqeust::DeviceList)
+            .await?
+            .result
+            .ok_or(MikitError::Unknown("parse data error".to_string()))?
+            .list;
+        self.db.set("devices", &devices)?;
+        Ok(devices)
+    }
+
+    /// 上一次`fetch_devices`缓存的设备列表
+    pub fn cached_devices(&self) -> anyhow::Result<Vec<Device>> {
+        self.db.get::<Vec<Device>>("devices")
+    }
+
+    pub async fn homes(&self) -> anyhow::Result<Vec<Home>> {
+        Ok(self
+            .execute_command::<CommandResponse<HomeListResult>>(CommandReqeust::HomeList)
+            .await?
+            .result
+            .ok_or(MikitError::Unknown("unable to get home list".to_string()))?
+            .homelist)
+    }
+
+    pub async fn rooms(&self, home_id: &str) -> anyhow::Result<Vec<Room>> {
+        self.homes()
+            .await?
+            .into_iter()
+            .find(|home| home.id == home_id)
+            .map(|home| home.rooms)
+            .ok_or(MikitError::Unknown(format!("home {} not found", home_id)).into())
+    }
+
+    /// 获取所有家庭的设备并按房间分组
+    pub async fn devices_by_room(&self) -> anyhow::Result<Vec<RoomDevices>> {
+        let homes = self.homes().await?;
+        let devices = self.fetch_devices().await?;
+        Ok(group_devices_by_room(&homes, &devices))
+    }
+
+    /// 获取指定名称房间中的设备，名称不区分大小写
+    pub async fn devices_in_room(&self, room_name: &str) -> anyhow::Result<Vec<Device>> {
+        Ok(self
+            .devices_by_room()
+            .await?
+            .into_iter()
+            .filter(|group| {
+                group
+                    .room
+                    .as_ref()
+                    .is_some_and(|room| room.name.eq_ignore_ascii_case(room_name))
+            })
+            .flat_map(|group| group.devices)
+            .collect())
+    }
+
+    pub async fn list_scenes(&self, home_id: &str) -> anyhow::Result<Vec<Scene>> {
+        Ok(self
+            .execute_command::<CommandResponse<SceneListResult>>(CommandReqeust::SceneList(
+                home_id.to_string(),
+            ))
+            .await?
+            .result
+            .ok_or(MikitError::Unknown("unable to get scene list".to_string()))?
+            .scene_info_list)
+    }
+
+    /// 手动触发场景，云端拒绝时返回`MikitError::Scene`
+    pub async fn run_scene(&self, scene_id: &str) -> anyhow::Result<()> {
+        let response = self
+            .execute_command::<CommandResponse<Value>>(CommandReqeust::RunScene(
+                scene_id.to_string(),
+            ))
+            .await?;
+        if response.code != 0 || response.result == Some(Value::Bool(false)) {
+            return Err(MikitError::Scene(scene_id.to_string(), response.message).into());
+        }
+        Ok(())
+    }
+
+    /// 获取家庭中设备的耗材状态，使用默认阈值判断是否需要更换
+    pub async fn consumables(&self, home: &Home) -> anyhow::Result<Vec<DeviceConsumables>> {
+        self.consumables_with_threshold(home, ConsumableThreshold::default())
+            .await
+    }
+
+    pub async fn consumables_with_threshold(
+        &self,
+        home: &Home,
+        threshold: ConsumableThreshold,
+    ) -> anyhow::Result<Vec<DeviceConsumables>> {
+        let home_id = home
+            .id
+            .parse::<u64>()
+            .map_err(|_| MikitError::Unknown(format!("invalid home id:{}", home.id)))?;
+        let items = self
+            .execute_command::<CommandResponse<ConsumableListResult>>(CommandReqeust::Consumables(
+                ConsumableRequestParams {
+                    home_id,
+                    owner_id: home.uid,
+                },
+            ))
+            .await?
+            .result
+            .ok_or(MikitError::Unknown("unable to get consumables".to_string()))?
+            .items;
+        Ok(items
+            .into_iter()
+            .map(|device| DeviceConsumables {
+                did: device.did,
+                items: device
+                    .consumes_data
+                    .iter()
+                    .map(|raw| parse_consumable(raw, threshold))
+                    .collect(),
+            })
+            .collect())
+    }
+
+    /// 查询设备上报到云端的历史数据，`key`形如`prop.temperature`或`event.motion`，
+    /// 不带前缀时按属性查询。自动分页，返回时间范围内最新的`limit`条记录，按时间升序排列
+    pub async fn device_history(
+        &self,
+        did: &str,
+        key: &str,
+        time_range: Range<u64>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<HistoryRecord>> {
+        let (kind, key) = match key.split_once('.') {
+            Some(("prop", key)) => (HistoryKind::Prop, key),
+            Some(("event", key)) => (HistoryKind::Event, key),
+            _ => (HistoryKind::Prop, key),
+        };
+        let mut records: Vec<HistoryRecord> = vec![];
+        let mut time_end = time_range.end.saturating_sub(1);
+        // 分页时`time_end`包含在内，上一页中时间等于`time_end`的记录会再次返回，需要去重
+        let mut boundary: Vec<HistoryRecord> = vec![];
+        while records.len() < limit && time_end >= time_range.start {
+            let page_limit = (limit - records.len() + boundary.len()).min(HISTORY_PAGE_SIZE);
+            let page = self
+                .execute_command::<CommandResponse<Vec<HistoryRecord>>>(CommandReqeust::DeviceData(
+                    DeviceDataRequestParams {
+                        did: did.to_string(),
+                        key: key.to_string(),
+                        kind,
+                        time_start: time_range.start,
+                        time_end,
+                        limit: page_limit,
+                    },
+                ))
+                .await?
+                .result
+                .unwrap_or_default();
+            let page_len = page.len();
+            let Some(earliest) = page.iter().map(|record| record.time).min() else {
+                break;
+            };
+            let fresh: Vec<HistoryRecord> = page
+                .into_iter()
+                .filter(
+                    |record| match boundary.iter().position(|seen| seen == record) {
+                        Some(idx) => {
+                            boundary.swap_remove(idx);
+                            false
+                        }
+                        None => true,
+                    },
+                )
+                .take(limit - records.len())
+                .collect();
+            let fresh_len = fresh.len();
+            records.extend(fresh);
+            if page_len < page_limit {
+                break;
+            }
+            if fresh_len == 0 {
+                // 同一时间的记录超过了单页上限，无法继续分页，跳过该时间点剩余的记录
+                trace!("too many history records at {}", earliest);
+                if earliest == 0 {
+                    break;
+                }
+                time_end = earliest - 1;
+                boundary.clear();
+                continue;
+            }
+            time_end = earliest;
+            boundary = records
+                .iter()
+                .filter(|record| record.time == earliest)
+                .cloned()
+                .collect();
+        }
+        records.sort_by_key(|record| record.time);
+        Ok(records)
+    }
+
+    /// 查询设备的聚合统计数据，例如插座的用电量，按时间升序返回
+    pub async fn statistics(
+        &self,
+        did: &str,
+        key: &str,
+        granularity: Granularity,
+        time_range: Range<u64>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<StatPoint>> {
+        let mut points = self
+            .execute_command::<CommandResponse<Vec<StatPoint>>>(CommandReqeust::Statistics(
+                StatisticsRequestParams {
+                    did: did.to_string(),
+                    key: key.to_string(),
+                    data_type: granularity,
+                    time_start: time_range.start,
+                    time_end: time_range.end.saturating_sub(1),
+                    limit,
+                },
+            ))
+            .await?
+            .result
+            .unwrap_or_default();
+        points.sort_by_key(|point| point.time);
+        Ok(points)
+    }
+
+    /// 获取型号的MIoT spec，解析结果缓存在本地，之后离线也可以使用
+    pub async fn device_spec(&self, model: &str) -> anyhow::Result<DeviceSpec> {
+        let key = spec_key(model);
+        if let Result::Ok(spec) = self.store.get::<DeviceSpec>(&key) {
+            return Ok(spec);
+        }
+        let spec = match &self.spec_options.source {
+            SpecSource::Server(url) => {
+                let instances = self
+                    .spec_instances
+                    .get_or_try_init(|| self.http_client.fetch_spec_instances(url))
+                    .await?;
+                let instance = instances
+                    .iter()
+                    .filter(|instance| instance.model == model)
+                    .max_by_key(|instance| instance.version)
+                    .ok_or_else(|| spec_not_found(model))?;
+                let value = self.http_client.fetch_spec(url, &instance.urn).await?;
+                DeviceSpec::from_value(model, value)?
+            }
+            SpecSource::Local(path) => read_local_spec(path, model)?,
+        };
+        self.store.set(&key, &spec)?;
+        Ok(spec)
+    }
+
+    pub async fn model_info(&self, model: &str) -> anyhow::Result<ModelInfo> {
+        Ok(self.device_spec(model).await?.model_info())
+    }
+
+    /// 删除缓存的spec，下次使用时重新获取
+    pub fn clear_spec_cache(&self, model: &str) -> anyhow::Result<()> {
+        self.store.remove(&spec_key(model))
+    }
+
+    pub fn region(&self) -> Region {
+        self.http_client.region()
+    }
+
+    /// 切换服务器区域，区域会和账号一起保存
+    pub fn set_region(&self, region: Region) -> anyhow::Result<()> {
+        self.db.set("region", &region)?;
+        self.http_client.set_region(region);
+        Ok(())
+    }
+
+    /// 依次请求各区域的设备列表，选择第一个有设备的区域并保存
+    pub async fn detect_region(&self) -> anyhow::Result<Region> {
         if !self.is_logged() {
             return Err(MikitError::UnLogin.into());
         }
+        for region in Region::ALL {
+            let devices = self
+                .execute_command_in_region::<CommandResponse<DeviceListResult>>(
+                    CommandReqeust::DeviceList,
+                    region,
+                )
+                .await;
+            match devices {
+                Result::Ok(response) => {
+                    if response
+                        .result
+                        .is_some_and(|result| !result.list.is_empty())
+                    {
+                        self.set_region(region)?;
+                        return Ok(region);
+                    }
+                }
+                // 续期失败说明账号需要重新登录，其他区域也不会成功
+                Err(e) if is_auth_error(&e) => return Err(e),
+                Err(e) => trace!("probe region {} failed:{}", region.code(), e),
+            }
+        }
+        Err(MikitError::Unknown("can not find devices in any region".to_string()).into())
+    }
+
+    /// 读取设备属性，数量较多时分批并发请求，结果顺序与请求一致
+    /// 部分批次失败时对应属性的`code`为`PROPERTY_FAILED_CODE`，全部失败时返回错误
+    pub async fn get_device_properties(
+        &self,
+        device_properties: &[DeviceProperties],
+    ) -> anyhow::Result<Vec<DeviceProperties>> {
+        let batches: Vec<_> = stream::iter(device_properties.chunks(PROPERTIES_BATCH_SIZE))
+            .map(|chunk| async move {
+                let result = self
+                    .execute_command::<CommandResponse<Vec<DeviceProperties>>>(
+                        CommandReqeust::GetProperties(DevicePropertiesRequestParams {
+                            params: chunk.to_vec(),
+                        }),
+                    )
+                    .await
+                    .and_then(|response| {
+                        response.result.ok_or(
+                            MikitError::Unknown("unable to get device properties".to_string())
+                                .into(),
+                        )
+                    });
+                (chunk, result)
+            })
+            .buffered(PROPERTIES_CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut properties = Vec::with_capacity(device_properties.len());
+        let mut error = None;
+        let mut succeeded = false;
+        for (chunk, result) in batches {
+            match result {
+                Result::Ok(values) => {
+                    succeeded = true;
+                    properties.extend(merge_properties(chunk, values));
+                }
+                Err(e) => {
+                    trace!("get properties batch failed:{}", e);
+                    properties.extend(chunk.iter().map(failed_property));
+                    error = Some(e);
+                }
+            }
+        }
+        match error {
+            Some(e) if !succeeded => Err(e),
+            _ => Ok(properties),
+        }
+    }
+
+    pub async fn set_device_properties(
+        &self,
+        device_properties: &[DeviceProperties],
+    ) -> anyhow::Result<()> {
+        self.write_properties(device_properties).await?;
+        Ok(())
+    }
+
+    /// 按`服务名.属性名`读取属性，例如`light.brightness`，名称通过设备型号的spec解析，
+    /// 返回值按spec的格式转换并带有单位
+    pub async fn get_property(&self, did: &str, name: &str) -> anyhow::Result<PropertyValue> {
+        let (siid, property) = self.resolve_property(did, name).await?;
+        let value = self.read_property(did, siid, property.iid, name).await?;
+        Ok(PropertyValue {
+            value: property.decode(value),
+            unit: property.unit(),
+        })
+    }
+
+    /// 按`服务名.属性名`设置属性，例如`set_property(did, "light.on", true)`，
+    /// 写入前按spec转换值，例如`"on"`转为`true`，可选值的描述转为对应的值，
+    /// 不校验该型号时转换失败只记录日志，按原值写入
+    pub async fn set_property(
+        &self,
+        did: &str,
+        name: &str,
+        value: impl Into<MiotValue>,
+    ) -> anyhow::Result<()> {
+        let (siid, property) = self.resolve_property(did, name).await?;
+        let value = value.into();
+        let value = match property.coerce(value.clone()) {
+            Result::Ok(value) => value,
+            Err(issue) if !self.spec_options.validates(&self.device_model(did).await?) => {
+                warn!("write {} of device {} as is:{:?}", name, did, issue);
+                value
+            }
+            Err(issue) => {
+                return Err(MikitError::Validation(vec![PropertyValidationError {
+                    did: did.to_string(),
+                    siid,
+                    piid: property.iid,
+                    issue,
+                }])
+                .into())
+            }
+        };
+        self.write_property(did, siid, property.iid, value, name)
+            .await
+    }
+
+    /// 按siid和piid读取单个属性，设备返回错误码时返回`MikitError::Property`
+    pub async fn get_property_by_iid(
+        &self,
+        did: &str,
+        siid: usize,
+        piid: usize,
+    ) -> anyhow::Result<MiotValue> {
+        self.read_property(did, siid, piid, &iid_name(siid, piid))
+            .await
+    }
+
+    /// 按siid和piid设置单个属性，设备返回错误码时返回`MikitError::Property`
+    pub async fn set_property_by_iid(
+        &self,
+        did: &str,
+        siid: usize,
+        piid: usize,
+        value: impl Into<MiotValue>,
+    ) -> anyhow::Result<()> {
+        self.write_property(did, siid, piid, value.into(), &iid_name(siid, piid))
+            .await
+    }
+
+    /// 调用设备的MIoT action，例如扫地机开始清扫、音箱播报文字等
+    pub async fn call_action(
+        &self,
+        did: &str,
+        siid: usize,
+        aiid: usize,
+        in_args: &[MiotValue],
+    ) -> anyhow::Result<ActionResult> {
+        self.execute_command::<CommandResponse<ActionResult>>(CommandReqeust::Action(
+            ActionRequestParams {
+                params: DeviceAction::new(did, siid, aiid, in_args.to_vec()),
+            },
+        ))
+        .await?
+        .result
+        .ok_or(MikitError::Unknown("unable to call device action".to_string()).into())
+    }
+
+    /// 向任意云端接口发送签名请求，用于本库尚未封装的接口，`uri`不包含`/app`前缀
+    pub async fn raw_request(
+        &self,
+        uri: &str,
+        data: &Value,
+    ) -> anyhow::Result<CommandResponse<Value>> {
+        self.execute_command::<CommandResponse<Value>>(CommandReqeust::Raw(
+            uri.to_string(),
+            data.clone(),
+        ))
+        .await
+    }
+
+    /// 通过云端调用旧版miIO协议的方法，返回设备响应中的`result`
+    pub async fn call_rpc(&self, did: &str, method: &str, params: Value) -> anyhow::Result<Value> {
+        let response = self
+            .execute_command::<CommandResponse<Value>>(CommandReqeust::Rpc(RpcRequestParams {
+                did: did.to_string(),
+                method: method.to_string(),
+                params,
+            }))
+            .await?;
+        if response.code != 0 {
+            return Err(MikitError::Rpc(response.code, response.message).into());
+        }
+        Ok(response.result.unwrap_or(Value::Null))
+    }
+
+    /// `miIO.info`，返回固件版本、网络等设备信息
+    pub async fn rpc_info(&self, did: &str) -> anyhow::Result<Value> {
+        self.call_rpc(did, "miIO.info", Value::Array(vec![])).await
+    }
+
+    /// `get_prop`，按请求顺序返回属性值
+    pub async fn rpc_get_prop(&self, did: &str, props: &[&str]) -> anyhow::Result<Vec<Value>> {
+        let result = self
+            .call_rpc(did, "get_prop", serde_json::json!(props))
+            .await?;
+        match result {
+            Value::Array(values) => Ok(values),
+            value => {
+                Err(MikitError::Unknown(format!("unexpected get_prop result:{}", value)).into())
+            }
+        }
+    }
+
+    pub async fn rpc_set_power(&self, did: &str, on: bool) -> anyhow::Result<()> {
+        let power = if on { "on" } else { "off" };
+        let result = self
+            .call_rpc(did, "set_power", serde_json::json!([power]))
+            .await?;
+        expect_rpc_ok("set_power", result)
+    }
+
+    /// 扫地机等设备的`app_start`
+    pub async fn rpc_app_start(&self, did: &str) -> anyhow::Result<()> {
+        let result = self
+            .call_rpc(did, "app_start", Value::Array(vec![]))
+            .await?;
+        expect_rpc_ok("app_start", result)
+    }
+
+    pub async fn rpc_app_stop(&self, did: &str) -> anyhow::Result<()> {
+        let result = self.call_rpc(did, "app_stop", Value::Array(vec![])).await?;
+        expect_rpc_ok("app_stop", result)
+    }
+
+    /// 退出当前账号并清除该账号的数据，其他账号不受影响
+    pub fn logout(&mut self) -> anyhow::Result<()> {
+        let mut account = self.account.write().unwrap();
+        *account = None;
+        self.is_logged.store(false, Ordering::Relaxed);
+        self.db.clear()
+    }
+
+    /// 导出当前登录会话（账号、区域、设备id），提供口令时导出内容会被加密
+    pub fn export_session(&self, passphrase: Option<&str>) -> anyhow::Result<String> {
+        let account = self.get_account().ok_or(MikitError::UnLogin)?;
+        let bundle = SessionBundle {
+            version: SESSION_VERSION,
+            device_id: account.device_id.clone(),
+            account,
+            region: self.region(),
+        };
+        encode_session(&bundle, passphrase)
+    }
+
+    pub fn export_session_to_file(
+        &self,
+        path: impl AsRef<Path>,
+        passphrase: Option<&str>,
+    ) -> anyhow::Result<()> {
+        fs::write(path, self.export_session(passphrase)?)?;
+        Ok(())
+    }
+
+    /// 导入`export_session`导出的会话，请求一次设备列表验证会话有效后保存
+    pub async fn import_session(
+        &self,
+        token: &str,
+        passphrase: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let bundle = decode_session(token, passphrase)?;
+        let mut account = bundle.account;
+        account.device_id = bundle.device_id;
         let client = self.http_client.clone();
-        let account = self.get_account().unwrap();
-        Ok(client
-            .execute_command::<CommandResponse<DeviceListResult>>(
+        let response = client
+            .execute_command_in_region::<CommandResponse<Value>>(
                 CommandReqeust::DeviceList,
                 &account,
+                bundle.region,
             )
-            .await?
-            .result
-            .ok_or(MikitError::Unknown("parse data error".to_string()))?
-            .list)
+            .await?;
+        if response.code != 0 {
+            return Err(MikitError::Session(format!(
+                "session rejected, code:{} message:{}",
+                response.code, response.message
+            ))
+            .into());
+        }
+        self.save_account(&account)?;
+        self.set_region(bundle.region)
     }
 
-    pub async fn get_device_properties(
+    pub async fn import_session_from_file(
         &self,
-        device_properties: &[DeviceProperties],
-    ) -> anyhow::Result<Vec<DeviceProperties>> {
+        path: impl AsRef<Path>,
+        passphrase: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let token = fs::read_to_string(path)?;
+        self.import_session(&token, passphrase).await
+    }
+
+    pub fn get_account(&self) -> Option<MiAccount> {
+        let account = self.account.clone();
+        let account = account.read().unwrap();
+        account.as_ref().cloned()
+    }
+
+    pub fn is_logged(&self) -> bool {
+        self.is_logged.load(Ordering::Relaxed)
+    }
+
+    async fn execute_command<T: DeserializeOwned>(
+        &self,
+        command: CommandReqeust,
+    ) -> anyhow::Result<T> {
+        self.execute_command_in_region(command, self.region()).await
+    }
+
+    /// 在指定区域执行命令，登录态过期时自动用`passToken`续期、保存新账号并重试一次
+    async fn execute_command_in_region<T: DeserializeOwned>(
+        &self,
+        command: CommandReqeust,
+        region: Region,
+    ) -> anyhow::Result<T> {
         if !self.is_logged() {
             return Err(MikitError::UnLogin.into());
         }
         let client = self.http_client.clone();
         let account = self.get_account().unwrap();
-        client
+        match client
+            .execute_command_in_region::<T>(command.clone(), &account, region)
+            .await
+        {
+            Err(e) if MikitError::is_auth_expired(&e) => {
+                let account = self.refresh_account(&account).await?;
+                client
+                    .execute_command_in_region::<T>(command, &account, region)
+                    .await
+            }
+            result => result,
+        }
+    }
+
+    /// 续期过期的账号，其他请求已经续期过时直接使用保存的新账号
+    async fn refresh_account(&self, expired: &MiAccount) -> anyhow::Result<MiAccount> {
+        let _guard = self.refresh_lock.lock().await;
+        if let Some(account) = self.get_account() {
+            if account.service_token != expired.service_token {
+                return Ok(account);
+            }
+        }
+        trace!("service token expired, try to refresh session");
+        let account = self.http_client.refresh_session(expired).await?;
+        self.save_account(&account)?;
+        Ok(account)
+    }
+
+    /// 读取单个属性并检查设备返回的结果码，没有返回值时视为失败
+    async fn read_property(
+        &self,
+        did: &str,
+        siid: usize,
+        piid: usize,
+        name: &str,
+    ) -> anyhow::Result<MiotValue> {
+        let result = self
+            .get_device_properties(&[DeviceProperties::new_get_properties(did, siid, piid)])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| MikitError::Property(name.to_string(), PROPERTY_FAILED_CODE))?;
+        match (result.code.unwrap_or(0), result.value) {
+            (0, Some(value)) => Ok(value),
+            (0, None) => Err(MikitError::Property(name.to_string(), PROPERTY_FAILED_CODE).into()),
+            (code, _) => Err(MikitError::Property(name.to_string(), code).into()),
+        }
+    }
+
+    /// 写入单个属性并检查设备返回的结果码，没有返回结果时视为失败
+    async fn write_property(
+        &self,
+        did: &str,
+        siid: usize,
+        piid: usize,
+        value: MiotValue,
+        name: &str,
+    ) -> anyhow::Result<()> {
+        let results = self
+            .write_properties(&[DeviceProperties::new_set_properties(did, siid, piid, value)])
+            .await?;
+        let code = results
+            .first()
+            .map_or(PROPERTY_FAILED_CODE, |result| result.code.unwrap_or(0));
+        match code {
+            0 => Ok(()),
+            code => Err(MikitError::Property(name.to_string(), code).into()),
+        }
+    }
+
+    async fn write_properties(
+        &self,
+        device_properties: &[DeviceProperties],
+    ) -> anyhow::Result<Vec<DeviceProperties>> {
+        self.validate_properties(device_properties).await?;
+        Ok(self
             .execute_command::<CommandResponse<Vec<DeviceProperties>>>(
-                CommandReqeust::GetProperties(DevicePropertiesRequestParams {
+                CommandReqeust::SetProperties(DevicePropertiesRequestParams {
                     params: device_properties.to_vec(),
                 }),
-                &account,
             )
             .await?
             .result
-            .ok_or(MikitError::Unknown("unable to get device properties".to_string()).into())
+            .unwrap_or_default())
     }
 
-    pub async fn set_device_properties(
-        &mut self,
+    /// 根据设备型号的spec校验要写入的属性，型号没有spec的设备不做校验
+    async fn validate_properties(
+        &self,
         device_properties: &[DeviceProperties],
     ) -> anyhow::Result<()> {
-        if !self.is_logged() {
-            return Err(MikitError::UnLogin.into());
+        if !self.spec_options.validate {
+            return Ok(());
         }
-        let client = self.http_client.clone();
-        let account = self.get_account().unwrap();
-        client
-            .execute_command::<CommandResponse<Value>>(
-                CommandReqeust::SetProperties(DevicePropertiesRequestParams {
-                    params: device_properties.to_vec(),
-                }),
-                &account,
-            )
-            .await?;
+        let mut specs: HashMap<String, Option<DeviceSpec>> = HashMap::new();
+        let mut errors = vec![];
+        for property in device_properties {
+            if !specs.contains_key(&property.did) {
+                let spec = self.validation_spec(&property.did).await?;
+                specs.insert(property.did.clone(), spec);
+            }
+            let Some(spec) = specs[&property.did].as_ref() else {
+                continue;
+            };
+            let issue = match (
+                spec.property_by_iid(property.siid, property.piid),
+                property.value.as_ref(),
+            ) {
+                (None, _) => Some(ValidationIssue::UnknownProperty),
+                (Some(_), None) => Some(ValidationIssue::MissingValue),
+                (Some(spec_property), Some(value)) => spec_property.validate(value).err(),
+            };
+            if let Some(issue) = issue {
+                errors.push(PropertyValidationError {
+                    did: property.did.clone(),
+                    siid: property.siid,
+                    piid: property.piid,
+                    issue,
+                });
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(MikitError::Validation(errors).into())
+        }
+    }
+
+    /// 校验使用的spec，型号没有spec时不做校验，记录下来之后直接跳过，
+    /// 其他错误直接返回，下次写入时重新获取
+    async fn validation_spec(&self, did: &str) -> anyhow::Result<Option<DeviceSpec>> {
+        if self.unvalidated_devices.read().unwrap().contains(did) {
+            return Ok(None);
+        }
+        let model = self.device_model(did).await?;
+        if !self.spec_options.validates(&model) {
+            return Ok(None);
+        }
+        match self.device_spec(&model).await {
+            Result::Ok(spec) => Ok(Some(spec)),
+            Err(e) if MikitError::is_spec_not_found(&e) => {
+                warn!("skip property validation of device {}:{}", did, e);
+                self.unvalidated_devices
+                    .write()
+                    .unwrap()
+                    .insert(did.to_string());
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 设备的型号，优先从缓存的设备列表中查找
+    async fn device_model(&self, did: &str) -> anyhow::Result<String> {
+        let cached = self.cached_devices().unwrap_or_default();
+        if let Some(device) = cached.iter().find(|device| device.did == did) {
+            return Ok(device.model.clone());
+        }
+        self.fetch_devices()
+            .await?
+            .into_iter()
+            .find(|device| device.did == did)
+            .map(|device| device.model)
+            .ok_or_else(|| MikitError::UnknownDevice(did.to_string()).into())
+    }
+
+    async fn resolve_property(
+        &self,
+        did: &str,
+        name: &str,
+    ) -> anyhow::Result<(usize, SpecProperty)> {
+        let model = self.device_model(did).await?;
+        let spec = self.device_spec(&model).await?;
+        spec.property(name)
+            .map(|(siid, property)| (siid, property.clone()))
+            .ok_or_else(|| MikitError::UnknownProperty(model, name.to_string()).into())
+    }
+
+    fn save_account(&self, account: &MiAccount) -> anyhow::Result<()> {
+        let db = self.db.clone();
+        db.set_secret("account", account)?;
+
+        let mut guard = self.account.write().unwrap();
+        *guard = Some(account.clone());
+
+        self.is_logged.store(true, Ordering::Relaxed);
         Ok(())
     }
+}
 
-    pub fn logout(&mut self) -> anyhow::Result<()> {
-        let mut account = self.account.write().unwrap();
-        *account = None;
-        self.db.clear()
+/// 控制类的miIO方法成功时返回`["ok"]`
+fn expect_rpc_ok(method: &str, result: Value) -> anyhow::Result<()> {
+    if result == serde_json::json!(["ok"]) || result == Value::String("ok".to_string()) {
+        return Ok(());
     }
+    Err(MikitError::Rpc(-1, format!("{} returned {}", method, result)).into())
+}
 
-    pub fn get_account(&self) -> Option<MiAccount> {
-        let account = self.account.clone();
-        let account = account.read().unwrap();
-        account.as_ref().and_then(|value| Some(value.clone()))
+/// 按请求顺序排列云端返回的属性，缺失的属性标记为失败
+fn merge_properties(
+    requested: &[DeviceProperties],
+    mut values: Vec<DeviceProperties>,
+) -> Vec<DeviceProperties> {
+    requested
+        .iter()
+        .map(|request| {
+            values
+                .iter()
+                .position(|value| {
+                    value.did == request.did
+                        && value.siid == request.siid
+                        && value.piid == request.piid
+                })
+                .map(|idx| values.swap_remove(idx))
+                .unwrap_or_else(|| failed_property(request))
+        })
+        .collect()
+}
+
+fn failed_property(request: &DeviceProperties) -> DeviceProperties {
+    DeviceProperties {
+        value: None,
+        code: Some(PROPERTY_FAILED_CODE),
+        ..request.clone()
     }
+}
 
-    pub fn is_logged(&self) -> bool {
-        self.is_logged.load(Ordering::Relaxed)
+fn parse_consumable(raw: &RawConsumable, threshold: ConsumableThreshold) -> ConsumableItem {
+    let text = match &raw.value {
+        Value::String(value) => value.trim().to_string(),
+        value => value.to_string(),
+    };
+    let number = text
+        .trim_end_matches(|c: char| !c.is_ascii_digit() && c != '.')
+        .parse::<f64>()
+        .ok();
+    let is_days = text.ends_with('天') || text.to_lowercase().ends_with("days");
+    let (remaining_percent, remaining_days) = if is_days {
+        (None, number)
+    } else {
+        (number, None)
+    };
+    let needs_replacement = remaining_percent.is_some_and(|percent| percent <= threshold.percent)
+        || remaining_days.is_some_and(|days| days <= threshold.days);
+    ConsumableItem {
+        name: raw.description.clone(),
+        remaining_percent,
+        remaining_days,
+        needs_replacement,
+    }
+}
+
+fn group_devices_by_room(homes: &[Home], devices: &[Device]) -> Vec<RoomDevices> {
+    let find_devices = |dids: &[String]| -> Vec<Device> {
+        devices
+            .iter()
+            .filter(|device| dids.contains(&device.did))
+            .cloned()
+            .collect()
+    };
+    let mut groups = vec![];
+    for home in homes {
+        for room in home.rooms.iter() {
+            groups.push(RoomDevices {
+                home_id: home.id.clone(),
+                room: Some(room.clone()),
+                devices: find_devices(&room.dids),
+            });
+        }
+        let unassigned: Vec<String> = home
+            .dids
+            .iter()
+            .filter(|did| !home.rooms.iter().any(|room| room.dids.contains(did)))
+            .cloned()
+            .collect();
+        if !unassigned.is_empty() {
+            groups.push(RoomDevices {
+                home_id: home.id.clone(),
+                room: None,
+                devices: find_devices(&unassigned),
+            });
+        }
+    }
+    groups
+}
+
+enum StorageLocation {
+    Application {
+        application_name: String,
+        organization_name: String,
+    },
+    Path(PathBuf),
+}
+
+/// `MiKit`的构建器，可以配置服务地址、超时、代理、根证书、UA以及存储位置
+pub struct MiKitBuilder {
+    http_options: HttpOptions,
+    storage: StorageLocation,
+    store_key: Option<StoreKey>,
+    spec_options: SpecOptions,
+}
+
+impl Default for MiKitBuilder {
+    fn default() -> Self {
+        Self {
+            http_options: HttpOptions::default(),
+            storage: StorageLocation::Application {
+                application_name: "mikit".to_string(),
+                organization_name: "com.nickming".to_string(),
+            },
+            store_key: None,
+            spec_options: SpecOptions::default(),
+        }
+    }
+}
+
+impl MiKitBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 小米账号服务地址，默认为`https://account.xiaomi.com`
+    pub fn account_api(mut self, url: &str) -> Self {
+        self.http_options.endpoints.account = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// 米家命令接口地址，设置后不再根据区域选择服务器
+    pub fn command_api(mut self, url: &str) -> Self {
+        self.http_options.endpoints.command = Some(url.trim_end_matches('/').to_string());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.http_options.timeout = Some(timeout);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.http_options.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// 代理地址，支持`http://`、`https://`和`socks5://`
+    pub fn proxy(mut self, url: &str) -> Self {
+        self.http_options.proxy = Some(url.to_string());
+        self
+    }
+
+    /// 添加PEM格式的根证书
+    pub fn add_root_certificate(mut self, pem: &[u8]) -> Self {
+        self.http_options.root_certificates.push(pem.to_vec());
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.http_options.user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    /// MIoT spec服务器地址
+    pub fn spec_api(mut self, url: &str) -> Self {
+        self.spec_options.source = SpecSource::Server(url.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// 从本地文件或目录读取MIoT spec，不再访问spec服务器
+    pub fn spec_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.spec_options.source = SpecSource::Local(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// 写入属性前是否根据spec校验，默认开启。无法获取型号或spec的设备不做校验
+    pub fn validate_properties(mut self, validate: bool) -> Self {
+        self.spec_options.validate = validate;
+        self
+    }
+
+    /// 跳过指定型号的属性校验，用于spec与设备实际行为不一致的型号
+    pub fn skip_validation(mut self, model: &str) -> Self {
+        self.spec_options.skip_validation.insert(model.to_string());
+        self
+    }
+
+    /// 命令接口使用的协议，默认优先使用加密协议，服务端拒绝时改用明文协议
+    pub fn protocol(mut self, protocol: ProtocolMode) -> Self {
+        self.http_options.protocol = protocol;
+        self
+    }
+
+    /// 数据保存在系统数据目录下以应用名和组织名区分的位置
+    pub fn application(mut self, application_name: &str, organization_name: &str) -> Self {
+        self.storage = StorageLocation::Application {
+            application_name: application_name.to_string(),
+            organization_name: organization_name.to_string(),
+        };
+        self
+    }
+
+    /// 数据保存在指定目录
+    pub fn storage_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.storage = StorageLocation::Path(path.into());
+        self
+    }
+
+    /// 开启加密存储
+    pub fn store_key(mut self, store_key: StoreKey) -> Self {
+        self.store_key = Some(store_key);
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<MiKit> {
+        let mut store = match self.storage {
+            StorageLocation::Application {
+                application_name,
+                organization_name,
+            } => DataSore::new(&application_name, &organization_name)?,
+            StorageLocation::Path(path) => DataSore::open(path)?,
+        };
+        if let Some(store_key) = self.store_key.as_ref() {
+            store.unlock(store_key)?;
+        }
+        migrate_legacy_account(&store)?;
+        let profile = store
+            .get::<String>(ACTIVE_PROFILE_KEY)
+            .unwrap_or(DEFAULT_PROFILE.to_string());
+        let http_client = HttpClient::new(&self.http_options)?;
+        MiKit::open_profile(
+            Arc::new(store),
+            &profile,
+            http_client,
+            Arc::new(self.spec_options),
+            Arc::new(OnceCell::new()),
+        )
+    }
+}
+
+/// 没有属性名时错误信息中使用`siid.piid`
+fn iid_name(siid: usize, piid: usize) -> String {
+    format!("{}.{}", siid, piid)
+}
+
+fn spec_key(model: &str) -> String {
+    format!("spec/{}", model)
+}
+
+fn is_auth_error(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<MikitError>(),
+        Some(MikitError::AuthExpired | MikitError::UnLogin)
+    )
+}
+
+fn is_store_key_error(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<MikitError>(),
+        Some(MikitError::InvalidStoreKey | MikitError::StoreKeyRequired)
+    )
+}
+
+fn register_profile(store: &DataSore, name: &str) -> anyhow::Result<()> {
+    store.transaction(|tx| {
+        let mut profiles = tx.get::<Vec<String>>(PROFILES_KEY)?.unwrap_or_default();
+        if !profiles.iter().any(|profile| profile == name) {
+            profiles.push(name.to_string());
+            tx.set(PROFILES_KEY, &profiles)?;
+        }
+        Result::Ok(())
+    })
+}
+
+fn migrate_legacy_account(store: &DataSore) -> anyhow::Result<()> {
+    if !store.contains("account")? {
+        return Ok(());
+    }
+    let default_db = store.scoped(DEFAULT_PROFILE)?;
+    if !default_db.contains("account")? {
+        default_db.set_secret("account", &store.get_secret::<MiAccount>("account")?)?;
+        if let Result::Ok(region) = store.get::<Region>("region") {
+            default_db.set("region", &region)?;
+        }
+    }
+    for key in LEGACY_KEYS {
+        store.remove(key)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use std::{env, fs};
+
+    use super::{
+        group_devices_by_room, merge_properties, parse_consumable, MiKit, MiKitBuilder,
+        PROPERTY_FAILED_CODE,
+    };
+    use crate::models::{
+        ConsumableThreshold, Device, DeviceProperties, Granularity, HistoryKind, Home, MiAccount,
+        MikitError, MiotValue, ProtocolMode, RawConsumable, Region, Room, SessionBundle, Unit,
+    };
+    use crate::session::{encode_session, SESSION_VERSION};
+    use crate::spec::test::LIGHT_SPEC;
+    use crate::spec::ValidationIssue;
+    use crate::stub_server::{
+        spawn_request_stub_server, spawn_stub_server, StubRequest, StubResponse,
+    };
+    use crate::utils::get_random_string;
+
+    /// 启动模拟服务并返回一个已登录、命令接口指向模拟服务的实例
+    async fn stub_kit<F>(handler: F) -> (MiKit, PathBuf)
+    where
+        F: Fn(&str, &str) -> StubResponse + Send + Sync + 'static,
+    {
+        stub_kit_with(handler, |builder| builder).await
+    }
+
+    async fn stub_kit_with<F>(
+        handler: F,
+        configure: impl FnOnce(MiKitBuilder) -> MiKitBuilder,
+    ) -> (MiKit, PathBuf)
+    where
+        F: Fn(&str, &str) -> StubResponse + Send + Sync + 'static,
+    {
+        stub_request_kit(move |request, base| handler(&request.path, base), configure).await
+    }
+
+    /// `handler`可以拿到完整的请求，用于检查请求参数
+    async fn stub_request_kit<F>(
+        handler: F,
+        configure: impl FnOnce(MiKitBuilder) -> MiKitBuilder,
+    ) -> (MiKit, PathBuf)
+    where
+        F: Fn(&StubRequest, &str) -> StubResponse + Send + Sync + 'static,
+    {
+        let base = spawn_request_stub_server(handler).await;
+        let path = env::temp_dir().join(format!("mikit_test_{}", get_random_string(8)));
+        let kit = configure(MiKit::builder())
+            .account_api(&base)
+            .command_api(&base)
+            .spec_api(&base)
+            .storage_path(&path)
+            .build()
+            .unwrap();
+        kit.save_account(&MiAccount {
+            user_id: "42".to_string(),
+            security_token: "c2VjdXJpdHk=".to_string(),
+            device_id: "device".to_string(),
+            service_token: "token".to_string(),
+            cookies: HashMap::new(),
+        })
+        .unwrap();
+        (kit, path)
+    }
+
+    #[tokio::test]
+    async fn test_builder_with_stub_server() {
+        let device_list_calls = Arc::new(AtomicUsize::new(0));
+        let calls = device_list_calls.clone();
+        let base = spawn_stub_server(move |path, base| {
+            if path.starts_with("/pass/serviceLogin") {
+                StubResponse::json(format!(
+                    r#"{{"code":0,"location":"{}/sts?d=1","nonce":1,"ssecurity":"c2VjdXJpdHk=","userId":42}}"#,
+                    base
+                ))
+            } else if path.starts_with("/sts") {
+                let mut response = StubResponse::text(200, "ok");
+                response
+                    .headers
+                    .push(("set-cookie", "serviceToken=renewed".to_string()));
+                response
+            } else if calls.fetch_add(1, Ordering::SeqCst) == 1 {
+                StubResponse::text(401, "auth err")
+            } else {
+                StubResponse::text(
+                    200,
+                    r#"{"code":0,"message":"ok","result":{"list":[{"name":"lamp","did":"1","token":"t","isOnline":true,"model":"yeelink.light.color1","localip":null}]}}"#,
+                )
+            }
+        })
+        .await;
+        let path = env::temp_dir().join(format!("mikit_builder_{}", get_random_string(8)));
+        let kit = MiKit::builder()
+            .account_api(&base)
+            .command_api(&base)
+            .timeout(Duration::from_secs(5))
+            .storage_path(&path)
+            .build()
+            .unwrap();
+        let bundle = SessionBundle {
+            version: SESSION_VERSION,
+            account: MiAccount {
+                user_id: "42".to_string(),
+                security_token: "c2VjdXJpdHk=".to_string(),
+                device_id: "device".to_string(),
+                service_token: "expired".to_string(),
+                cookies: HashMap::from([("passToken".to_string(), "pass".to_string())]),
+            },
+            region: Region::Sg,
+            device_id: "device".to_string(),
+        };
+        let token = encode_session(&bundle, None).unwrap();
+        kit.import_session(&token, None).await.unwrap();
+        assert_eq!(Region::Sg, kit.region());
+
+        let devices = kit.fetch_devices().await.unwrap();
+        assert_eq!("yeelink.light.color1", devices[0].model);
+        assert_eq!(3, device_list_calls.load(Ordering::SeqCst));
+        let account = kit.get_account().unwrap();
+        assert_eq!("renewed", account.service_token);
+        assert_eq!("device", account.device_id);
+        assert_eq!(1, kit.cached_devices().unwrap().len());
+
+        drop(kit);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_profiles() {
+        let (kit, path) = stub_kit(|_, _| StubResponse::text(404, "not found")).await;
+        let other = kit.profile("default").unwrap();
+        std::thread::scope(|scope| {
+            for idx in 0..8 {
+                let kit = if idx % 2 == 0 { &kit } else { &other };
+                scope.spawn(move || kit.add_profile(&format!("p{}", idx)).unwrap());
+            }
+        });
+        let mut profiles = kit.profiles().unwrap();
+        profiles.sort();
+        assert_eq!(
+            vec!["default", "p0", "p1", "p2", "p3", "p4", "p5", "p6", "p7"],
+            profiles
+        );
+
+        let mut switched = kit.profile("p0").unwrap();
+        switched.switch_profile("p1").unwrap();
+        let error = kit.remove_profile("p1").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::Profile(_))
+        ));
+        kit.remove_profile("p2").unwrap();
+        assert!(kit.profiles().unwrap().contains(&"p1".to_string()));
+        assert!(!kit.profiles().unwrap().contains(&"p2".to_string()));
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_detect_region_refresh() {
+        let refresh_ok = Arc::new(AtomicBool::new(true));
+        let device_list_calls = Arc::new(AtomicUsize::new(0));
+        let (handler_refresh, calls) = (refresh_ok.clone(), device_list_calls.clone());
+        let (kit, path) = stub_kit(move |path, base| {
+            if path.starts_with("/pass/serviceLogin") && handler_refresh.load(Ordering::SeqCst) {
+                StubResponse::json(format!(
+                    r#"{{"code":0,"location":"{}/sts?d=1","nonce":1,"ssecurity":"c2VjdXJpdHk=","userId":42}}"#,
+                    base
+                ))
+            } else if path.starts_with("/pass/serviceLogin") {
+                StubResponse::json(r#"{"code":70016,"desc":"login required"}"#.to_string())
+            } else if path.starts_with("/sts") {
+                let mut response = StubResponse::text(200, "ok");
+                response
+                    .headers
+                    .push(("set-cookie", "serviceToken=renewed".to_string()));
+                response
+            } else if calls.fetch_add(1, Ordering::SeqCst) % 2 == 0 {
+                StubResponse::text(401, "auth err")
+            } else {
+                StubResponse::text(
+                    200,
+                    r#"{"code":0,"message":"ok","result":{"list":[{"name":"lamp","did":"1","token":"t","isOnline":true,"model":"yeelink.light.color1","localip":null}]}}"#,
+                )
+            }
+        })
+        .await;
+        assert_eq!(Region::ALL[0], kit.detect_region().await.unwrap());
+        assert_eq!(2, device_list_calls.load(Ordering::SeqCst));
+        assert_eq!("renewed", kit.get_account().unwrap().service_token);
+
+        refresh_ok.store(false, Ordering::SeqCst);
+        let error = kit.detect_region().await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::UnLogin)
+        ));
+        assert_eq!(3, device_list_calls.load(Ordering::SeqCst));
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_call_action() {
+        let (kit, path) = stub_request_kit(
+            |request, _| {
+                if !request.path.starts_with("/miotspec/action") {
+                    return StubResponse::text(404, "not found");
+                }
+                let data: serde_json::Value =
+                    serde_json::from_str(&request.form("data").unwrap_or_default()).unwrap();
+                assert_eq!(
+                    serde_json::json!({"params":{"did":"1","siid":5,"aiid":1,"in":["hello"]}}),
+                    data
+                );
+                StubResponse::text(
+                    200,
+                    r#"{"code":0,"message":"ok","result":{"did":"1","siid":5,"aiid":1,"code":0,"out":[true]}}"#,
+                )
+            },
+            |builder| builder.protocol(ProtocolMode::Plain),
+        )
+        .await;
+        let result = kit.call_action("1", 5, 1, &["hello".into()]).await.unwrap();
+        assert!(result.is_success());
+        assert_eq!(vec![MiotValue::Bool(true)], result.out);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_scenes() {
+        let (kit, path) = stub_request_kit(
+            |request, _| {
+                let data: serde_json::Value =
+                    serde_json::from_str(&request.form("data").unwrap_or_default()).unwrap();
+                if request.path.ends_with("/GetSceneList") {
+                    assert_eq!(serde_json::json!({"home_id":"100"}), data);
+                    StubResponse::text(
+                        200,
+                        r#"{"code":0,"message":"ok","result":{"scene_info_list":[{"scene_id":123,"scene_name":"Good night","home_id":100,"scene_type":1}]}}"#,
+                    )
+                } else if request.path.ends_with("/RunScene") && data["scene_id"] == "123" {
+                    StubResponse::text(200, r#"{"code":0,"message":"ok","result":true}"#)
+                } else if request.path.ends_with("/RunScene") {
+                    StubResponse::text(200, r#"{"code":0,"message":"ok","result":false}"#)
+                } else {
+                    StubResponse::text(404, "not found")
+                }
+            },
+            |builder| builder.protocol(ProtocolMode::Plain),
+        )
+        .await;
+        let scenes = kit.list_scenes("100").await.unwrap();
+        assert_eq!(1, scenes.len());
+        assert_eq!(
+            ("123", "Good night", "100", "1"),
+            (
+                scenes[0].id.as_str(),
+                scenes[0].name.as_str(),
+                scenes[0].home_id.as_str(),
+                scenes[0].trigger_type.as_str()
+            )
+        );
+        kit.run_scene("123").await.unwrap();
+        let error = kit.run_scene("456").await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::Scene(scene_id, _)) if scene_id == "456"
+        ));
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_group_devices_by_room() {
+        let device = |did: &str| Device {
+            name: did.to_string(),
+            did: did.to_string(),
+            token: "".to_string(),
+            is_online: true,
+            model: "".to_string(),
+            localip: None,
+        };
+        let homes = vec![Home {
+            id: "home".to_string(),
+            name: "home".to_string(),
+            uid: 42,
+            dids: vec!["3".to_string()],
+            rooms: vec![Room {
+                id: "kitchen".to_string(),
+                name: "Kitchen".to_string(),
+                dids: vec!["1".to_string(), "2".to_string()],
+            }],
+        }];
+        let devices = vec![device("1"), device("2"), device("3"), device("4")];
+        let groups = group_devices_by_room(&homes, &devices);
+        assert_eq!(2, groups.len());
+        assert_eq!("Kitchen", groups[0].room.as_ref().unwrap().name);
+        assert_eq!(2, groups[0].devices.len());
+        assert!(groups[1].room.is_none());
+        assert_eq!("3", groups[1].devices[0].did);
+    }
+
+    #[tokio::test]
+    async fn test_device_history_pagination() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let server_calls = calls.clone();
+        let (kit, path) = stub_kit(move |_, _| {
+            let times: Vec<u64> = match server_calls.fetch_add(1, Ordering::SeqCst) {
+                0 => (1001..=2000).rev().collect(),
+                _ => vec![1000, 999],
+            };
+            let records: Vec<String> = times
+                .iter()
+                .map(|time| {
+                    format!(
+                        r#"{{"did":"1","type":"prop","key":"temperature","value":"[{}.5]","time":{}}}"#,
+                        time, time
+                    )
+                })
+                .collect();
+            StubResponse::text(
+                200,
+                &format!(r#"{{"code":0,"message":"ok","result":[{}]}}"#, records.join(",")),
+            )
+        })
+        .await;
+        let records = kit
+            .device_history("1", "prop.temperature", 0..3000, 5000)
+            .await
+            .unwrap();
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+        assert_eq!(1002, records.len());
+        assert_eq!(999, records[0].time);
+        assert_eq!(HistoryKind::Prop, records[0].kind);
+        assert_eq!(vec![MiotValue::Float(999.5)], records[0].value);
+        assert_eq!(2000, records.last().unwrap().time);
+
+        drop(kit);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_device_history_equal_timestamps() {
+        // 第一页的最后一条记录落在5条同一时间的记录中间
+        let mut data: Vec<(u64, usize)> = (1002..=2000).rev().map(|time| (time, 0)).collect();
+        data.extend((1..=5).map(|idx| (1000, idx)));
+        data.push((999, 0));
+        let (kit, path) = stub_request_kit(
+            move |request, _| {
+                let params: serde_json::Value =
+                    serde_json::from_str(&request.form("data").unwrap_or_default()).unwrap();
+                let time_end = params["time_end"].as_u64().unwrap();
+                let limit = params["limit"].as_u64().unwrap() as usize;
+                let records: Vec<String> = data
+                    .iter()
+                    .filter(|(time, _)| *time <= time_end)
+                    .take(limit)
+                    .map(|(time, idx)| {
+                        format!(
+                            r#"{{"did":"1","type":"event","key":"motion","value":"[{}]","time":{}}}"#,
+                            idx, time
+                        )
+                    })
+                    .collect();
+                StubResponse::text(
+                    200,
+                    &format!(r#"{{"code":0,"message":"ok","result":[{}]}}"#, records.join(",")),
+                )
+            },
+            |builder| builder.protocol(ProtocolMode::Plain),
+        )
+        .await;
+        let records = kit
+            .device_history("1", "event.motion", 0..3000, 5000)
+            .await
+            .unwrap();
+        assert_eq!(1005, records.len());
+        let mut equal: Vec<i64> = records
+            .iter()
+            .filter(|record| record.time == 1000)
+            .map(|record| record.value[0].as_i64().unwrap())
+            .collect();
+        equal.sort();
+        assert_eq!(vec![1, 2, 3, 4, 5], equal);
+        assert_eq!(999, records[0].time);
+
+        let records = kit
+            .device_history("1", "event.motion", 0..3000, 1003)
+            .await
+            .unwrap();
+        assert_eq!(1003, records.len());
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_call_rpc() {
+        let (kit, path) = stub_request_kit(
+            |request, _| {
+                let data: serde_json::Value =
+                    serde_json::from_str(&request.form("data").unwrap_or_default()).unwrap();
+                match (request.path.as_str(), data["method"].as_str()) {
+                    ("/home/rpc/1", Some("get_prop")) => {
+                        assert_eq!(serde_json::json!(["power", "bright"]), data["params"]);
+                        StubResponse::text(200, r#"{"code":0,"message":"ok","result":["on",80]}"#)
+                    }
+                    ("/home/rpc/1", Some("set_power")) => {
+                        StubResponse::text(200, r#"{"code":0,"message":"ok","result":["ok"]}"#)
+                    }
+                    ("/home/rpc/1", Some("app_start")) => {
+                        StubResponse::text(200, r#"{"code":0,"message":"ok","result":["busy"]}"#)
+                    }
+                    _ => StubResponse::text(
+                        200,
+                        r#"{"code":-2,"message":"device offline","result":null}"#,
+                    ),
+                }
+            },
+            |builder| builder.protocol(ProtocolMode::Plain),
+        )
+        .await;
+        let values = kit.rpc_get_prop("1", &["power", "bright"]).await.unwrap();
+        assert_eq!(vec![serde_json::json!("on"), serde_json::json!(80)], values);
+        kit.rpc_set_power("1", true).await.unwrap();
+        let error = kit.rpc_app_start("1").await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::Rpc(-1, _))
+        ));
+        let error = kit.rpc_info("2").await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::Rpc(-2, message)) if message == "device offline"
+        ));
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_consumable() {
+        let consumable = |value: serde_json::Value| RawConsumable {
+            description: "filter".to_string(),
+            value,
+        };
+        let threshold = ConsumableThreshold::default();
+        let item = parse_consumable(&consumable(serde_json::json!("80%")), threshold);
+        assert_eq!(Some(80.0), item.remaining_percent);
+        assert!(!item.needs_replacement);
+
+        let item = parse_consumable(&consumable(serde_json::json!(5)), threshold);
+        assert_eq!(Some(5.0), item.remaining_percent);
+        assert!(item.needs_replacement);
+
+        let item = parse_consumable(&consumable(serde_json::json!("3天")), threshold);
+        assert_eq!(None, item.remaining_percent);
+        assert_eq!(Some(3.0), item.remaining_days);
+        assert!(item.needs_replacement);
+    }
+
+    #[tokio::test]
+    async fn test_statistics() {
+        let (kit, path) = stub_kit(|_, _| {
+            StubResponse::text(
+                200,
+                r#"{"code":0,"message":"ok","result":[{"value":"[0.8]","time":1700003600},{"value":"[12.3]","time":1700000000}]}"#,
+            )
+        })
+        .await;
+        let points = kit
+            .statistics(
+                "1",
+                "power_cost",
+                Granularity::Hour,
+                1700000000..1700007200,
+                24,
+            )
+            .await
+            .unwrap();
+        assert_eq!(2, points.len());
+        assert_eq!(1700000000, points[0].time);
+        assert_eq!(12.3, points[0].value);
+        assert_eq!(0.8, points[1].value);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_raw_request() {
+        let (kit, path) = stub_kit(|path, _| {
+            if path.ends_with("/v2/custom/endpoint") {
+                StubResponse::text(200, r#"{"code":0,"message":"ok","result":{"answer":42}}"#)
+            } else {
+                StubResponse::text(404, "not found")
+            }
+        })
+        .await;
+        let response = kit
+            .raw_request(
+                "v2/custom/endpoint",
+                &serde_json::json!({ "question": true }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(0, response.code);
+        assert_eq!(Some(serde_json::json!({ "answer": 42 })), response.result);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_merge_properties() {
+        let requested = vec![
+            DeviceProperties::new_get_properties("1", 2, 1),
+            DeviceProperties::new_get_properties("1", 2, 2),
+            DeviceProperties::new_get_properties("2", 3, 1),
+        ];
+        let values: Vec<DeviceProperties> = serde_json::from_str(
+            r#"[{"did":"2","siid":3,"piid":1,"value":true,"code":0},
+                {"did":"1","siid":2,"piid":1,"code":-704030013}]"#,
+        )
+        .unwrap();
+        let merged = merge_properties(&requested, values);
+        assert_eq!(3, merged.len());
+        assert_eq!(
+            ("1", 1, Some(-704030013)),
+            (merged[0].did.as_str(), merged[0].piid, merged[0].code)
+        );
+        assert_eq!(Some(PROPERTY_FAILED_CODE), merged[1].code);
+        assert_eq!(Some(MiotValue::Bool(true)), merged[2].value);
+        assert_eq!(Some(0), merged[2].code);
+    }
+
+    #[tokio::test]
+    async fn test_get_device_properties_batches() {
+        let (kit, path) = stub_request_kit(
+            |request, _| {
+                let data: serde_json::Value =
+                    serde_json::from_str(&request.form("data").unwrap_or_default()).unwrap();
+                let piids: Vec<u64> = data["params"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|param| param["piid"].as_u64().unwrap())
+                    .collect();
+                assert!(piids.len() <= 100);
+                if piids.contains(&150) {
+                    return StubResponse::text(500, "internal error");
+                }
+                let results: Vec<String> = piids
+                    .iter()
+                    .rev()
+                    .map(|piid| {
+                        format!(
+                            r#"{{"did":"1","siid":2,"piid":{},"value":{},"code":0}}"#,
+                            piid, piid
+                        )
+                    })
+                    .collect();
+                StubResponse::text(
+                    200,
+                    &format!(
+                        r#"{{"code":0,"message":"ok","result":[{}]}}"#,
+                        results.join(",")
+                    ),
+                )
+            },
+            |builder| builder.protocol(ProtocolMode::Plain),
+        )
+        .await;
+        let requested: Vec<DeviceProperties> = (0..250)
+            .map(|piid| DeviceProperties::new_get_properties("1", 2, piid))
+            .collect();
+        let properties = kit.get_device_properties(&requested).await.unwrap();
+        assert_eq!(250, properties.len());
+        for (idx, property) in properties.iter().enumerate() {
+            assert_eq!(idx, property.piid);
+            if (100..200).contains(&idx) {
+                assert_eq!(Some(PROPERTY_FAILED_CODE), property.code);
+                assert_eq!(None, property.value);
+            } else {
+                assert_eq!(Some(0), property.code);
+                assert_eq!(Some(MiotValue::Int64(idx as i64)), property.value);
+            }
+        }
+
+        let error = kit.get_device_properties(&requested[100..200]).await;
+        assert!(error.is_err());
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_refresh() {
+        let refresh_calls = Arc::new(AtomicUsize::new(0));
+        let calls = refresh_calls.clone();
+        let (kit, path) = stub_request_kit(
+            move |request, base| {
+                if request.path.starts_with("/pass/serviceLogin") {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    return StubResponse::json(format!(
+                        r#"{{"code":0,"location":"{}/sts?d=1","nonce":1,"ssecurity":"c2VjdXJpdHk=","userId":42}}"#,
+                        base
+                    ));
+                }
+                if request.path.starts_with("/sts") {
+                    let mut response = StubResponse::text(200, "ok");
+                    response
+                        .headers
+                        .push(("set-cookie", "serviceToken=renewed".to_string()));
+                    return response;
+                }
+                if !request
+                    .header("cookie")
+                    .is_some_and(|cookie| cookie.contains("serviceToken=renewed"))
+                {
+                    return StubResponse::text(401, "auth err");
+                }
+                let data: serde_json::Value =
+                    serde_json::from_str(&request.form("data").unwrap_or_default()).unwrap();
+                let results: Vec<String> = data["params"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|param| {
+                        format!(
+                            r#"{{"did":"1","siid":2,"piid":{},"value":1,"code":0}}"#,
+                            param["piid"]
+                        )
+                    })
+                    .collect();
+                StubResponse::text(
+                    200,
+                    &format!(
+                        r#"{{"code":0,"message":"ok","result":[{}]}}"#,
+                        results.join(",")
+                    ),
+                )
+            },
+            |builder| builder.protocol(ProtocolMode::Plain),
+        )
+        .await;
+        let requested: Vec<DeviceProperties> = (0..400)
+            .map(|piid| DeviceProperties::new_get_properties("1", 2, piid))
+            .collect();
+        let properties = kit.get_device_properties(&requested).await.unwrap();
+        assert!(properties.iter().all(|property| property.code == Some(0)));
+        assert_eq!(1, refresh_calls.load(Ordering::SeqCst));
+        assert_eq!("renewed", kit.get_account().unwrap().service_token);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_device_spec_cache() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let server_calls = calls.clone();
+        let (kit, path) = stub_kit(move |path, _| {
+            server_calls.fetch_add(1, Ordering::SeqCst);
+            if path.starts_with("/instances") {
+                StubResponse::text(
+                    200,
+                    r#"{"instances":[
+                        {"model":"yeelink.light.color1","version":1,"type":"urn:old"},
+                        {"model":"yeelink.light.color1","version":2,"type":"urn:new"},
+                        {"model":"other.model","version":1,"type":"urn:other"}]}"#,
+                )
+            } else if path == "/instance?type=urn%3Anew" {
+                StubResponse::text(200, LIGHT_SPEC)
+            } else {
+                StubResponse::text(404, "not found")
+            }
+        })
+        .await;
+        let spec = kit.device_spec("yeelink.light.color1").await.unwrap();
+        assert_eq!("yeelink.light.color1", spec.model);
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+
+        let info = kit.model_info("yeelink.light.color1").await.unwrap();
+        assert_eq!(4, info.props.len());
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+
+        // 型号列表只请求一次，之后只需要请求spec本身
+        kit.clear_spec_cache("yeelink.light.color1").unwrap();
+        kit.device_spec("yeelink.light.color1").await.unwrap();
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+        assert!(kit.device_spec("unknown.model").await.is_err());
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_property_by_name() {
+        let (kit, path) = stub_kit(|path, _| {
+            if path.starts_with("/home/device_list") {
+                StubResponse::text(
+                    200,
+                    r#"{"code":0,"message":"ok","result":{"list":[{"name":"Lamp","did":"1","token":"t","isOnline":true,"model":"yeelink.light.color1"}]}}"#,
+                )
+            } else if path.starts_with("/instances") {
+                StubResponse::text(
+                    200,
+                    r#"{"instances":[{"model":"yeelink.light.color1","version":1,"type":"urn:light"}]}"#,
+                )
+            } else if path.starts_with("/instance") {
+                StubResponse::text(200, LIGHT_SPEC)
+            } else if path.starts_with("/miotspec/prop/get") {
+                StubResponse::text(
+                    200,
+                    r#"{"code":0,"message":"ok","result":[{"did":"1","siid":2,"piid":2,"value":80,"code":0}]}"#,
+                )
+            } else if path.starts_with("/miotspec/prop/set") {
+                StubResponse::text(
+                    200,
+                    r#"{"code":0,"message":"ok","result":[{"did":"1","siid":2,"piid":1,"code":-4004}]}"#,
+                )
+            } else {
+                StubResponse::text(404, "not found")
+            }
+        })
+        .await;
+        let brightness = kit.get_property("1", "light.brightness").await.unwrap();
+        assert_eq!(MiotValue::Uint8(80), brightness.value);
+        assert_eq!(Some(Unit::Percentage), brightness.unit);
+        assert_eq!("80 %", brightness.to_string());
+
+        let error = kit.get_property("1", "light.missing").await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::UnknownProperty(_, name)) if name == "light.missing"
+        ));
+        let error = kit.get_property("2", "light.on").await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::UnknownDevice(_))
+        ));
+        let error = kit.set_property("1", "light.on", "on").await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::Property(_, -4004))
+        ));
+        let error = kit
+            .set_property("1", "light.mode", "reading")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::Validation(errors)) if errors[0].issue == ValidationIssue::NotInValueList
+        ));
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_set_property_without_validation() {
+        let written = Arc::new(Mutex::new(vec![]));
+        let handler = |written: Arc<Mutex<Vec<serde_json::Value>>>| {
+            let light = light_handler(Arc::new(AtomicUsize::new(0)));
+            move |request: &StubRequest, base: &str| {
+                if !request.path.starts_with("/miotspec/prop/set") {
+                    return light(&request.path, base);
+                }
+                let data: serde_json::Value =
+                    serde_json::from_str(&request.form("data").unwrap_or_default()).unwrap();
+                written
+                    .lock()
+                    .unwrap()
+                    .push(data["params"][0]["value"].clone());
+                StubResponse::text(
+                    200,
+                    r#"{"code":0,"message":"ok","result":[{"did":"1","siid":2,"piid":3,"code":0}]}"#,
+                )
+            }
+        };
+        let (kit, path) = stub_request_kit(handler(written.clone()), |builder| {
+            builder
+                .protocol(ProtocolMode::Plain)
+                .skip_validation("yeelink.light.color1")
+        })
+        .await;
+        kit.set_property("1", "light.mode", "reading")
+            .await
+            .unwrap();
+        kit.set_property("1", "light.mode", "night").await.unwrap();
+        fs::remove_dir_all(path).unwrap();
+
+        let (kit, path) = stub_request_kit(handler(written.clone()), |builder| {
+            builder
+                .protocol(ProtocolMode::Plain)
+                .validate_properties(false)
+        })
+        .await;
+        kit.set_property("1", "light.mode", 7).await.unwrap();
+        assert_eq!(
+            vec![
+                serde_json::json!("reading"),
+                serde_json::json!(1),
+                serde_json::json!(7)
+            ],
+            *written.lock().unwrap()
+        );
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    fn light_handler(set_calls: Arc<AtomicUsize>) -> impl Fn(&str, &str) -> StubResponse {
+        move |path, _| {
+            if path.starts_with("/home/device_list") {
+                StubResponse::text(
+                    200,
+                    r#"{"code":0,"message":"ok","result":{"list":[{"name":"Lamp","did":"1","token":"t","isOnline":true,"model":"yeelink.light.color1"}]}}"#,
+                )
+            } else if path.starts_with("/instances") {
+                StubResponse::text(
+                    200,
+                    r#"{"instances":[{"model":"yeelink.light.color1","version":1,"type":"urn:light"}]}"#,
+                )
+            } else if path.starts_with("/instance") {
+                StubResponse::text(200, LIGHT_SPEC)
+            } else if path.starts_with("/miotspec/prop/set") {
+                set_calls.fetch_add(1, Ordering::SeqCst);
+                StubResponse::text(200, r#"{"code":0,"message":"ok","result":[]}"#)
+            } else {
+                StubResponse::text(404, "not found")
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_set_property_without_result() {
+        let set_calls = Arc::new(AtomicUsize::new(0));
+        let (kit, path) = stub_kit(light_handler(set_calls.clone())).await;
+        let error = kit.set_property("1", "light.on", true).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::Property(_, code)) if *code == PROPERTY_FAILED_CODE
+        ));
+        assert_eq!(1, set_calls.load(Ordering::SeqCst));
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_property_by_iid_codes() {
+        let light = light_handler(Arc::new(AtomicUsize::new(0)));
+        let (kit, path) = stub_kit(move |path, base| {
+            if path.starts_with("/miotspec/prop/get") {
+                StubResponse::text(
+                    200,
+                    r#"{"code":0,"message":"ok","result":[{"did":"1","siid":2,"piid":1,"code":-704042011}]}"#,
+                )
+            } else if path.starts_with("/miotspec/prop/set") {
+                StubResponse::text(
+                    200,
+                    r#"{"code":0,"message":"ok","result":[{"did":"1","siid":2,"piid":1,"code":-704030013}]}"#,
+                )
+            } else {
+                light(path, base)
+            }
+        })
+        .await;
+        let error = kit.get_property_by_iid("1", 2, 1).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::Property(name, -704042011)) if name == "2.1"
+        ));
+        let error = kit.set_property_by_iid("1", 2, 1, true).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::Property(name, -704030013)) if name == "2.1"
+        ));
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_validation_spec_errors() {
+        let set_calls = Arc::new(AtomicUsize::new(0));
+        let spec_available = Arc::new(AtomicBool::new(false));
+        let available = spec_available.clone();
+        let handler = light_handler(set_calls.clone());
+        let (kit, path) = stub_kit(move |path, base| {
+            if path.starts_with("/home/device_list") {
+                StubResponse::text(
+                    200,
+                    r#"{"code":0,"message":"ok","result":{"list":[{"name":"Lamp","did":"1","token":"t","isOnline":true,"model":"yeelink.light.color1"},{"name":"Plug","did":"2","token":"t","isOnline":true,"model":"unknown.plug"}]}}"#,
+                )
+            } else if path.starts_with("/instances") && !available.load(Ordering::SeqCst) {
+                StubResponse::text(500, "unavailable")
+            } else {
+                handler(path, base)
+            }
+        })
+        .await;
+        // spec服务暂时不可用时不写入，也不会跳过之后的校验
+        let properties = [DeviceProperties::new_set_properties("1", 2, 2, 120)];
+        let error = kit.set_device_properties(&properties).await.unwrap_err();
+        assert!(!matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::Validation(_))
+        ));
+        assert_eq!(0, set_calls.load(Ordering::SeqCst));
+        spec_available.store(true, Ordering::SeqCst);
+        let error = kit.set_device_properties(&properties).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::Validation(_))
+        ));
+
+        let properties = [DeviceProperties::new_set_properties("2", 2, 1, true)];
+        kit.set_device_properties(&properties).await.unwrap();
+        kit.set_device_properties(&properties).await.unwrap();
+        assert_eq!(2, set_calls.load(Ordering::SeqCst));
+
+        let properties = [DeviceProperties::new_set_properties("3", 2, 1, true)];
+        let error = kit.set_device_properties(&properties).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::UnknownDevice(did)) if did == "3"
+        ));
+        assert_eq!(2, set_calls.load(Ordering::SeqCst));
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_validate_properties() {
+        let properties = [
+            DeviceProperties::new_set_properties("1", 2, 1, true),
+            DeviceProperties::new_set_properties("1", 2, 2, 120),
+            DeviceProperties::new_set_properties("1", 2, 4, 4000),
+            DeviceProperties::new_set_properties("1", 9, 1, 1),
+        ];
+        let set_calls = Arc::new(AtomicUsize::new(0));
+        let (kit, path) = stub_kit(light_handler(set_calls.clone())).await;
+        let error = kit.set_device_properties(&properties).await.unwrap_err();
+        let Some(MikitError::Validation(errors)) = error.downcast_ref::<MikitError>() else {
+            panic!("unexpected error:{}", error);
+        };
+        let issues: Vec<(usize, usize, &ValidationIssue)> = errors
+            .iter()
+            .map(|error| (error.siid, error.piid, &error.issue))
+            .collect();
+        assert_eq!(3, issues.len());
+        assert!(matches!(issues[0], (2, 2, ValidationIssue::OutOfRange(_))));
+        assert_eq!((2, 4, &ValidationIssue::NotWritable), issues[1]);
+        assert_eq!((9, 1, &ValidationIssue::UnknownProperty), issues[2]);
+        assert_eq!(0, set_calls.load(Ordering::SeqCst));
+        kit.set_device_properties(&properties[..1]).await.unwrap();
+        assert_eq!(1, set_calls.load(Ordering::SeqCst));
+        fs::remove_dir_all(path).unwrap();
+
+        let (kit, path) = stub_kit_with(light_handler(set_calls.clone()), |builder| {
+            builder.skip_validation("yeelink.light.color1")
+        })
+        .await;
+        kit.set_device_properties(&properties).await.unwrap();
+        assert_eq!(2, set_calls.load(Ordering::SeqCst));
+        fs::remove_dir_all(path).unwrap();
+
+        let (kit, path) = stub_kit_with(light_handler(set_calls.clone()), |builder| {
+            builder.validate_properties(false)
+        })
+        .await;
+        kit.set_device_properties(&properties).await.unwrap();
+        assert_eq!(3, set_calls.load(Ordering::SeqCst));
+        fs::remove_dir_all(path).unwrap();
     }
 }
@@ -1,5 +1,10 @@
+pub mod codegen;
 pub mod kit;
 pub mod models;
 mod network;
+mod session;
+pub mod spec;
 mod store;
+#[cfg(test)]
+mod stub_server;
 mod utils;
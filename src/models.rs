@@ -1,9 +1,16 @@
 use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 use thiserror::Error;
 
+use crate::spec::PropertyValidationError;
+
 #[derive(Error, Debug)]
 pub enum MikitError {
     #[error("network error")]
@@ -16,6 +23,84 @@ pub enum MikitError {
     Store(#[from] sled::Error),
     #[error("unlogin eror")]
     UnLogin,
+    #[error("login failed, code:{0} desc:{1}")]
+    Login(i64, String),
+    #[error("identity verification error:{0}")]
+    Verification(String),
+    #[error("service token expired")]
+    AuthExpired,
+    #[error("encrypted request rejected with status {0}, the request was not executed")]
+    EncryptionRejected(u16),
+    #[error("profile error:{0}")]
+    Profile(String),
+    #[error("invalid store key")]
+    InvalidStoreKey,
+    #[error("encrypted data requires a store key")]
+    StoreKeyRequired,
+    #[error("session error:{0}")]
+    Session(String),
+    #[error("scene {0} failed to start:{1}")]
+    Scene(String, String),
+    #[error("rpc call failed, code:{0} message:{1}")]
+    Rpc(i64, String),
+    #[error("spec error:{0}")]
+    Spec(String),
+    #[error("spec of {0} not found")]
+    SpecNotFound(String),
+    #[error("device {0} not found")]
+    UnknownDevice(String),
+    #[error("property {1} not found in spec of {0}")]
+    UnknownProperty(String, String),
+    #[error("property {0} failed, code:{1}")]
+    Property(String, i64),
+    #[error("property validation failed:{0:?}")]
+    Validation(Vec<PropertyValidationError>),
+}
+
+impl MikitError {
+    /// 判断一个`anyhow::Error`是否由登录态过期导致
+    pub fn is_auth_expired(error: &anyhow::Error) -> bool {
+        matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::AuthExpired)
+        )
+    }
+
+    pub fn is_encryption_rejected(error: &anyhow::Error) -> bool {
+        matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::EncryptionRejected(_))
+        )
+    }
+
+    pub fn is_spec_not_found(error: &anyhow::Error) -> bool {
+        matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::SpecNotFound(_))
+        )
+    }
+}
+
+static KEY_FILE_ENV: &str = "MIKIT_KEY_FILE";
+static PASSPHRASE_ENV: &str = "MIKIT_PASSPHRASE";
+
+/// 加密存储使用的密钥来源
+#[derive(Clone, Debug)]
+pub enum StoreKey {
+    /// 通过PBKDF2从口令派生密钥
+    Passphrase(String),
+    /// 密钥文件，内容为32字节的原始密钥或其base64编码
+    KeyFile(PathBuf),
+}
+
+impl StoreKey {
+    /// 从环境变量`MIKIT_KEY_FILE`或`MIKIT_PASSPHRASE`读取密钥来源
+    pub fn from_env() -> Option<StoreKey> {
+        if let Ok(path) = env::var(KEY_FILE_ENV) {
+            return Some(StoreKey::KeyFile(PathBuf::from(path)));
+        }
+        env::var(PASSPHRASE_ENV).ok().map(StoreKey::Passphrase)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -27,6 +112,77 @@ pub struct MiAccount {
     pub cookies: HashMap<String, String>,
 }
 
+/// 可导出到其他机器使用的登录会话
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionBundle {
+    pub version: u32,
+    pub account: MiAccount,
+    pub region: Region,
+    pub device_id: String,
+}
+
+/// 调用命令接口时使用的协议
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProtocolMode {
+    /// 明文参数，使用HMAC-SHA256签名
+    Plain,
+    /// 参数和响应使用RC4加密
+    Encrypted,
+    /// 优先使用加密协议，服务端拒绝加密请求时改用明文协议
+    #[default]
+    Auto,
+}
+
+/// 米家云服务所在的服务器区域
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Region {
+    #[default]
+    Cn,
+    De,
+    Us,
+    Sg,
+    Ru,
+    Tw,
+    I2,
+}
+
+impl Region {
+    pub const ALL: [Region; 7] = [
+        Region::Cn,
+        Region::De,
+        Region::Us,
+        Region::Sg,
+        Region::Ru,
+        Region::Tw,
+        Region::I2,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Region::Cn => "cn",
+            Region::De => "de",
+            Region::Us => "us",
+            Region::Sg => "sg",
+            Region::Ru => "ru",
+            Region::Tw => "tw",
+            Region::I2 => "i2",
+        }
+    }
+}
+
+impl FromStr for Region {
+    type Err = MikitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Region::ALL
+            .into_iter()
+            .find(|region| region.code().eq_ignore_ascii_case(s))
+            .ok_or(MikitError::Unknown(format!("unsupported region:{}", s)))
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AccountSignatureResponse {
     pub qs: String,
@@ -38,13 +194,122 @@ pub struct AccountSignatureResponse {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AccountLoginResponse {
-    pub code: u8,
+    pub code: i64,
+    #[serde(default)]
     pub desc: String,
+    #[serde(default)]
     pub nonce: u128,
+    #[serde(default)]
     pub location: String,
-    #[serde(alias = "userId")]
+    #[serde(alias = "userId", default)]
     pub user_id: u64,
+    #[serde(default)]
     pub ssecurity: String,
+    #[serde(alias = "notificationUrl")]
+    pub notification_url: Option<String>,
+    #[serde(alias = "captchaUrl")]
+    pub captcha_url: Option<String>,
+    #[serde(alias = "passToken")]
+    pub pass_token: Option<String>,
+    /// 登录接口下发的`passToken`等cookie，用于登录态过期后免密续期
+    #[serde(skip)]
+    pub passport_cookies: HashMap<String, String>,
+}
+
+/// 登录结果，开启了二次验证的账号需要先完成身份验证
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum LoginOutcome {
+    Success(MiAccount),
+    VerificationRequired(VerificationChallenge),
+    CaptchaRequired(CaptchaChallenge),
+}
+
+/// 图形验证码，`cookies`和`signature`是重新提交登录表单所需的上下文
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CaptchaChallenge {
+    pub image: Vec<u8>,
+    pub cookies: HashMap<String, String>,
+    pub signature: AccountSignatureResponse,
+}
+
+/// 二次验证所需的上下文，需原样传回`MiKit::verify_login`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VerificationChallenge {
+    pub notification_url: String,
+    pub identity_session: String,
+    pub methods: Vec<VerificationMethod>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VerificationMethod {
+    Phone,
+    Email,
+}
+
+impl VerificationMethod {
+    pub(crate) fn from_flag(flag: u8) -> Option<Self> {
+        match flag {
+            4 => Some(VerificationMethod::Phone),
+            8 => Some(VerificationMethod::Email),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn flag(&self) -> u8 {
+        match self {
+            VerificationMethod::Phone => 4,
+            VerificationMethod::Email => 8,
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            VerificationMethod::Phone => "Phone",
+            VerificationMethod::Email => "Email",
+        }
+    }
+}
+
+/// 扫码登录的二维码信息，`timeout`为二维码有效期（秒）
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QrLoginTicket {
+    #[serde(alias = "qr")]
+    pub qr_image_url: String,
+    #[serde(alias = "loginUrl")]
+    pub login_url: String,
+    #[serde(alias = "lp")]
+    pub poll_url: String,
+    #[serde(default = "default_qr_timeout")]
+    pub timeout: u64,
+}
+
+fn default_qr_timeout() -> u64 {
+    300
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QrLoginResponse {
+    pub code: i64,
+    #[serde(default)]
+    pub desc: String,
+    #[serde(flatten)]
+    pub ticket: Option<QrLoginTicket>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IdentityListResponse {
+    pub code: i64,
+    pub flag: Option<u8>,
+    #[serde(default)]
+    pub options: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IdentityTicketResponse {
+    pub code: i64,
+    #[serde(alias = "tips", default)]
+    pub desc: String,
+    pub location: Option<String>,
 }
 // #[derive(Clone, Debug, Serialize, Deserialize)]
 // pub enum CommandResponse<T> {
@@ -54,7 +319,7 @@ pub struct AccountLoginResponse {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CommandResponse<T> {
-    pub code: usize,
+    pub code: i64,
     pub message: String,
     pub result: Option<T>,
 }
@@ -75,6 +340,234 @@ pub struct Device {
     pub localip: Option<String>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HomeListResult {
+    #[serde(default)]
+    pub homelist: Vec<Home>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Home {
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub uid: u64,
+    #[serde(default)]
+    pub dids: Vec<String>,
+    #[serde(alias = "roomlist", default)]
+    pub rooms: Vec<Room>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Room {
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub dids: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConsumableRequestParams {
+    pub home_id: u64,
+    pub owner_id: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConsumableListResult {
+    #[serde(default)]
+    pub items: Vec<RawDeviceConsumables>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RawDeviceConsumables {
+    pub did: String,
+    #[serde(default)]
+    pub consumes_data: Vec<RawConsumable>,
+}
+
+/// 云端返回的耗材数据，`value`可能是`80`、`"80%"`或`"12天"`等形式
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RawConsumable {
+    #[serde(alias = "name", default)]
+    pub description: String,
+    #[serde(default)]
+    pub value: Value,
+}
+
+/// 判断耗材需要更换的阈值，剩余百分比或剩余天数小于等于阈值时需要更换
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ConsumableThreshold {
+    pub percent: f64,
+    pub days: f64,
+}
+
+impl Default for ConsumableThreshold {
+    fn default() -> Self {
+        Self {
+            percent: 10.0,
+            days: 7.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeviceConsumables {
+    pub did: String,
+    pub items: Vec<ConsumableItem>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConsumableItem {
+    pub name: String,
+    pub remaining_percent: Option<f64>,
+    pub remaining_days: Option<f64>,
+    pub needs_replacement: bool,
+}
+
+/// 按房间分组的设备，`room`为None时表示家庭中未分配房间的设备
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RoomDevices {
+    pub home_id: String,
+    pub room: Option<Room>,
+    pub devices: Vec<Device>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SceneListResult {
+    #[serde(default)]
+    pub scene_info_list: Vec<Scene>,
+}
+
+/// 米家APP中配置的场景
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Scene {
+    #[serde(alias = "scene_id", deserialize_with = "string_or_number")]
+    pub id: String,
+    #[serde(alias = "scene_name")]
+    pub name: String,
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub home_id: String,
+    #[serde(alias = "scene_type", default, deserialize_with = "string_or_number")]
+    pub trigger_type: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeviceDataRequestParams {
+    pub did: String,
+    pub key: String,
+    #[serde(rename = "type")]
+    pub kind: HistoryKind,
+    pub time_start: u64,
+    pub time_end: u64,
+    pub limit: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryKind {
+    Prop,
+    Event,
+    #[serde(other)]
+    Other,
+}
+
+/// 设备上报到云端的一条历史数据，`time`为unix时间戳（秒）
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HistoryRecord {
+    pub time: u64,
+    #[serde(rename = "type")]
+    pub kind: HistoryKind,
+    pub key: String,
+    /// 属性记录为属性值，事件记录为各个参数的值
+    #[serde(deserialize_with = "history_value")]
+    pub value: Vec<MiotValue>,
+}
+
+impl HistoryRecord {
+    pub fn timestamp(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.time)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatisticsRequestParams {
+    pub did: String,
+    pub key: String,
+    pub data_type: Granularity,
+    pub time_start: u64,
+    pub time_end: u64,
+    pub limit: usize,
+}
+
+/// 统计数据的聚合粒度
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Granularity {
+    #[serde(rename = "stat_hour_v3")]
+    Hour,
+    #[serde(rename = "stat_day_v3")]
+    Day,
+    #[serde(rename = "stat_week_v3")]
+    Week,
+    #[serde(rename = "stat_month_v3")]
+    Month,
+}
+
+/// 一条统计数据，`time`为统计区间起始的unix时间戳（秒）
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatPoint {
+    pub time: u64,
+    #[serde(deserialize_with = "stat_value")]
+    pub value: f64,
+}
+
+impl StatPoint {
+    pub fn timestamp(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.time)
+    }
+}
+
+/// 统计数据中的`value`是json字符串，例如`"[12.3]"`，取第一个数值
+fn stat_value<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let value = json_string(deserializer)?;
+    let value = match value {
+        Value::Array(values) => values.into_iter().next().unwrap_or(Value::Null),
+        value => value,
+    };
+    match &value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.parse::<f64>().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| serde::de::Error::custom(format!("invalid statistics value:{}", value)))
+}
+
+/// 历史数据中的`value`是json字符串，例如`"[25.3]"`，解析失败时保留原字符串
+fn json_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::String(value) => Ok(serde_json::from_str(&value).unwrap_or(Value::String(value))),
+        value => Ok(value),
+    }
+}
+
+/// 历史数据中的`value`解析为`MiotValue`列表，不是数组时作为唯一的值
+fn history_value<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<MiotValue>, D::Error> {
+    match json_string(deserializer)? {
+        Value::Array(values) => Ok(values.into_iter().map(MiotValue::from).collect()),
+        value => Ok(vec![MiotValue::from(value)]),
+    }
+}
+
+/// 云端接口中的id有时是数字有时是字符串，统一解析为字符串
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::String(value) => Ok(value),
+        Value::Null => Ok(String::new()),
+        value => Ok(value.to_string()),
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DevicePropertiesRequestParams {
     pub params: Vec<DeviceProperties>,
@@ -85,36 +578,365 @@ pub struct DeviceProperties {
     pub did: String,
     pub siid: usize,
     pub piid: usize,
-    pub value: Option<Value>,
-    pub code: Option<usize>,
+    pub value: Option<MiotValue>,
+    pub code: Option<i64>,
     #[serde(alias = "in")]
-    pub action: Option<Value>,
+    pub action: Option<Vec<MiotValue>>,
 }
 
 impl DeviceProperties {
     pub fn new_get_properties(did: &str, siid: usize, piid: usize) -> Self {
         Self {
             did: did.to_string(),
-            siid: siid,
-            piid: piid,
+            siid,
+            piid,
             value: None,
             code: None,
             action: None,
         }
     }
 
-    pub fn new_set_properties(did: &str, siid: usize, piid: usize, value: Value) -> Self {
+    pub fn new_set_properties(
+        did: &str,
+        siid: usize,
+        piid: usize,
+        value: impl Into<MiotValue>,
+    ) -> Self {
         Self {
             did: did.to_string(),
-            siid: siid,
-            piid: piid,
-            value: Some(value),
+            siid,
+            piid,
+            value: Some(value.into()),
             code: None,
             action: None,
         }
     }
 }
 
+/// 旧版miIO协议的rpc请求
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RpcRequestParams {
+    #[serde(skip)]
+    pub did: String,
+    pub method: String,
+    pub params: Value,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ActionRequestParams {
+    pub params: DeviceAction,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeviceAction {
+    pub did: String,
+    pub siid: usize,
+    pub aiid: usize,
+    #[serde(rename = "in")]
+    pub in_args: Vec<MiotValue>,
+}
+
+impl DeviceAction {
+    pub fn new(did: &str, siid: usize, aiid: usize, in_args: Vec<MiotValue>) -> Self {
+        Self {
+            did: did.to_string(),
+            siid,
+            aiid,
+            in_args,
+        }
+    }
+}
+
+/// 调用action的结果，`code`为设备返回的结果码，0表示成功
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ActionResult {
+    #[serde(default)]
+    pub did: String,
+    pub siid: usize,
+    pub aiid: usize,
+    pub code: i64,
+    #[serde(default)]
+    pub out: Vec<MiotValue>,
+}
+
+impl ActionResult {
+    pub fn is_success(&self) -> bool {
+        self.code == 0
+    }
+
+    /// 将第index个输出参数解析为指定类型
+    pub fn out_value<T: DeserializeOwned>(&self, index: usize) -> Option<T> {
+        self.out
+            .get(index)
+            .and_then(|value| serde_json::from_value(value.to_json()).ok())
+    }
+}
+
+/// MIoT属性和动作参数的值，与json互相转换时数值保持不变，`Enum`转换为json时只保留数值
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(into = "Value", from = "Value")]
+pub enum MiotValue {
+    Bool(bool),
+    Uint8(u8),
+    Uint16(u16),
+    Uint32(u32),
+    /// spec中没有该格式，只用于保存超出`i64`范围的json整数
+    Uint64(u64),
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    Float(f64),
+    String(String),
+    /// spec中`value-list`的一项
+    Enum {
+        value: i64,
+        description: String,
+    },
+    /// 数组、对象等不属于MIoT格式的值，原样保留
+    Json(Value),
+}
+
+impl MiotValue {
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            MiotValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// 整数和枚举值，浮点数没有小数部分时也可以取到
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            MiotValue::Uint8(value) => Some(*value as i64),
+            MiotValue::Uint16(value) => Some(*value as i64),
+            MiotValue::Uint32(value) => Some(*value as i64),
+            MiotValue::Uint64(value) => i64::try_from(*value).ok(),
+            MiotValue::Int8(value) => Some(*value as i64),
+            MiotValue::Int16(value) => Some(*value as i64),
+            MiotValue::Int32(value) => Some(*value as i64),
+            MiotValue::Int64(value) => Some(*value),
+            MiotValue::Enum { value, .. } => Some(*value),
+            MiotValue::Float(value) if value.fract() == 0.0 => Some(*value as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            MiotValue::Float(value) => Some(*value),
+            MiotValue::Uint64(value) => Some(*value as f64),
+            value => value.as_i64().map(|value| value as f64),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            MiotValue::String(value) => Some(value),
+            MiotValue::Enum { description, .. } => Some(description),
+            _ => None,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            MiotValue::Bool(value) => Value::Bool(*value),
+            MiotValue::Uint64(value) => serde_json::json!(value),
+            MiotValue::Float(value) => serde_json::json!(value),
+            MiotValue::String(value) => Value::String(value.clone()),
+            MiotValue::Json(value) => value.clone(),
+            value => serde_json::json!(value.as_i64().unwrap_or_default()),
+        }
+    }
+}
+
+impl From<MiotValue> for Value {
+    fn from(value: MiotValue) -> Self {
+        value.to_json()
+    }
+}
+
+/// 不带spec转换时，整数统一为`Int64`，超出`i64`范围的正整数为`Uint64`，
+/// 其他值保存为`Json`
+impl From<Value> for MiotValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Bool(value) => MiotValue::Bool(value),
+            Value::String(value) => MiotValue::String(value),
+            Value::Number(number) => {
+                if let Some(value) = number.as_i64() {
+                    MiotValue::Int64(value)
+                } else if let Some(value) = number.as_u64() {
+                    MiotValue::Uint64(value)
+                } else {
+                    MiotValue::Float(number.as_f64().unwrap_or_default())
+                }
+            }
+            value => MiotValue::Json(value),
+        }
+    }
+}
+
+impl From<bool> for MiotValue {
+    fn from(value: bool) -> Self {
+        MiotValue::Bool(value)
+    }
+}
+
+impl From<u8> for MiotValue {
+    fn from(value: u8) -> Self {
+        MiotValue::Uint8(value)
+    }
+}
+
+impl From<u16> for MiotValue {
+    fn from(value: u16) -> Self {
+        MiotValue::Uint16(value)
+    }
+}
+
+impl From<u32> for MiotValue {
+    fn from(value: u32) -> Self {
+        MiotValue::Uint32(value)
+    }
+}
+
+impl From<i8> for MiotValue {
+    fn from(value: i8) -> Self {
+        MiotValue::Int8(value)
+    }
+}
+
+impl From<i16> for MiotValue {
+    fn from(value: i16) -> Self {
+        MiotValue::Int16(value)
+    }
+}
+
+impl From<i32> for MiotValue {
+    fn from(value: i32) -> Self {
+        MiotValue::Int32(value)
+    }
+}
+
+impl From<i64> for MiotValue {
+    fn from(value: i64) -> Self {
+        MiotValue::Int64(value)
+    }
+}
+
+impl From<f64> for MiotValue {
+    fn from(value: f64) -> Self {
+        MiotValue::Float(value)
+    }
+}
+
+impl From<&str> for MiotValue {
+    fn from(value: &str) -> Self {
+        MiotValue::String(value.to_string())
+    }
+}
+
+impl From<String> for MiotValue {
+    fn from(value: String) -> Self {
+        MiotValue::String(value)
+    }
+}
+
+impl fmt::Display for MiotValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MiotValue::String(value) => write!(f, "{}", value),
+            MiotValue::Enum { description, .. } if !description.is_empty() => {
+                write!(f, "{}", description)
+            }
+            value => write!(f, "{}", value.to_json()),
+        }
+    }
+}
+
+/// spec中属性的单位
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Unit {
+    Percentage,
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+    Seconds,
+    Minutes,
+    Hours,
+    Days,
+    Lux,
+    Pascal,
+    Ppm,
+    Watt,
+    Kwh,
+    Arcdegrees,
+    /// 没有单独定义的单位，保留spec中的原始名称
+    Other(String),
+}
+
+impl Unit {
+    /// spec中的`none`和空字符串表示没有单位
+    pub fn parse(unit: &str) -> Option<Unit> {
+        match unit {
+            "" | "none" => None,
+            "percentage" => Some(Unit::Percentage),
+            "celsius" => Some(Unit::Celsius),
+            "fahrenheit" => Some(Unit::Fahrenheit),
+            "kelvin" => Some(Unit::Kelvin),
+            "seconds" => Some(Unit::Seconds),
+            "minutes" => Some(Unit::Minutes),
+            "hours" => Some(Unit::Hours),
+            "days" => Some(Unit::Days),
+            "lux" => Some(Unit::Lux),
+            "pascal" => Some(Unit::Pascal),
+            "ppm" => Some(Unit::Ppm),
+            "watt" => Some(Unit::Watt),
+            "kwh" => Some(Unit::Kwh),
+            "arcdegrees" => Some(Unit::Arcdegrees),
+            unit => Some(Unit::Other(unit.to_string())),
+        }
+    }
+
+    pub fn symbol(&self) -> &str {
+        match self {
+            Unit::Percentage => "%",
+            Unit::Celsius => "°C",
+            Unit::Fahrenheit => "°F",
+            Unit::Kelvin => "K",
+            Unit::Seconds => "s",
+            Unit::Minutes => "min",
+            Unit::Hours => "h",
+            Unit::Days => "d",
+            Unit::Lux => "lx",
+            Unit::Pascal => "Pa",
+            Unit::Ppm => "ppm",
+            Unit::Watt => "W",
+            Unit::Kwh => "kWh",
+            Unit::Arcdegrees => "°",
+            Unit::Other(unit) => unit,
+        }
+    }
+}
+
+/// 按名称读取的属性值及其单位
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PropertyValue {
+    pub value: MiotValue,
+    pub unit: Option<Unit>,
+}
+
+impl fmt::Display for PropertyValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.unit.as_ref() {
+            Some(unit) => write!(f, "{} {}", self.value, unit.symbol()),
+            None => write!(f, "{}", self.value),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ModelInfo {
     pub name: String,
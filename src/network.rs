@@ -1,71 +1,462 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::Ok;
 use log::trace;
-use reqwest::header::{HeaderMap, HeaderValue};
-use reqwest::{Client, Response, Url};
+use reqwest::header::{HeaderMap, HeaderValue, DATE};
+use reqwest::{Client, Response, StatusCode, Url};
 use serde::de::DeserializeOwned;
+use serde_json::Value;
 
 use crate::models::{
-    AccountLoginResponse, AccountSignatureResponse, Device, DeviceProperties,
-    DevicePropertiesRequestParams, MiAccount, MikitError,
+    AccountLoginResponse, AccountSignatureResponse, ActionRequestParams, CaptchaChallenge,
+    ConsumableRequestParams, DeviceDataRequestParams, DevicePropertiesRequestParams,
+    IdentityListResponse, IdentityTicketResponse, LoginOutcome, MiAccount, MikitError,
+    ProtocolMode, QrLoginResponse, QrLoginTicket, Region, RpcRequestParams,
+    StatisticsRequestParams, VerificationChallenge, VerificationMethod,
 };
+use crate::spec::{SpecInstance, SpecInstanceList};
 use crate::utils::{
-    encode_to_base64, encrypt_with_md5, encrypt_with_sha1, generate_command_signature,
-    generate_nonce, generate_signed_nonce, get_random_string,
+    decrypt_with_rc4, encode_to_base64, encrypt_with_md5, encrypt_with_rc4, encrypt_with_sha1,
+    generate_command_signature, generate_encrypted_signature, generate_nonce,
+    generate_signed_nonce, get_random_string,
 };
 
 static BASE_UA: &str = "APP/com.xiaomi.mihome APPV/6.0.103 iosPassportSDK/3.9.0 iOS/14.4 miHSTS";
-static COMMAND_API: &str = "https://api.io.mi.com/app";
-static SIGNATURE_API: &str = "https://account.xiaomi.com/pass/serviceLogin";
-static LOGIN_API: &str = "https://account.xiaomi.com/pass/serviceLoginAuth2";
+static COMMAND_HOST: &str = "api.io.mi.com";
+static ACCOUNT_API: &str = "https://account.xiaomi.com";
+static SIGNATURE_PATH: &str = "/pass/serviceLogin";
+static LOGIN_PATH: &str = "/pass/serviceLoginAuth2";
+static IDENTITY_PATH: &str = "/identity";
+static QR_LOGIN_PATH: &str = "/longPolling/loginUrl";
+static STS_CALLBACK: &str = "https://sts.api.io.mi.com/sts";
+/// 扫码登录单次长轮询的超时时间，超时后继续轮询直到二维码过期
+static QR_POLL_TIMEOUT: Duration = Duration::from_secs(30);
+/// 轮询接口返回错误状态码时的重试间隔，每次失败后翻倍
+static QR_POLL_RETRY_DELAY: Duration = Duration::from_secs(1);
+static QR_POLL_MAX_RETRY_DELAY: Duration = Duration::from_secs(16);
+static JSON_PREFIX: &str = "&&&START&&&";
+static MAX_REDIRECTS: usize = 10;
+/// 接口不支持加密协议时返回的状态码，此时请求没有被执行，可以改用明文协议重发
+static ENCRYPTION_REJECTED_STATUS: [StatusCode; 2] = [
+    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+    StatusCode::NOT_IMPLEMENTED,
+];
+/// 重新登录时需要带上的通行证cookie
+static PASSPORT_COOKIES: [&str; 4] = ["passToken", "userId", "cUserId", "deviceId"];
+
+/// 各服务的基础地址，测试时可以指向本地的模拟服务
+#[derive(Clone, Debug)]
+pub struct Endpoints {
+    pub account: String,
+    /// 设置后所有命令都发往该地址，不再根据区域选择
+    pub command: Option<String>,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self {
+            account: ACCOUNT_API.to_string(),
+            command: None,
+        }
+    }
+}
+
+/// 构建`HttpClient`的配置
+#[derive(Clone, Debug, Default)]
+pub struct HttpOptions {
+    pub endpoints: Endpoints,
+    pub user_agent: Option<String>,
+    pub timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    /// 代理地址，支持`http://`、`https://`和`socks5://`
+    pub proxy: Option<String>,
+    /// PEM格式的根证书
+    pub root_certificates: Vec<Vec<u8>>,
+    pub protocol: ProtocolMode,
+}
+
+impl HttpOptions {
+    fn client_builder(&self) -> anyhow::Result<reqwest::ClientBuilder> {
+        let mut builder =
+            reqwest::ClientBuilder::new().user_agent(self.user_agent.as_deref().unwrap_or(BASE_UA));
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(proxy) = self.proxy.as_ref() {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        for certificate in self.root_certificates.iter() {
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(certificate)?);
+        }
+        Ok(builder)
+    }
+}
 
 pub struct HttpClient {
     client: Client,
+    redirect_client: Client,
+    endpoints: Endpoints,
+    protocol: ProtocolMode,
+    /// `ProtocolMode::Auto`下各地址和接口实际可用的协议
+    resolved_protocols: RwLock<HashMap<String, ProtocolMode>>,
+    region: RwLock<Region>,
+    /// 服务器时间与本地时间的差值（秒），用于生成nonce
+    clock_offset: AtomicI64,
 }
 
 impl Default for HttpClient {
     fn default() -> Self {
-        let client = reqwest::ClientBuilder::new()
-            .user_agent(BASE_UA)
-            .build()
-            .unwrap();
-        Self { client }
+        HttpClient::new(&HttpOptions::default()).unwrap()
     }
 }
 
 impl HttpClient {
-    pub async fn login(&self, username: &str, password: &str) -> anyhow::Result<MiAccount> {
+    pub fn new(options: &HttpOptions) -> anyhow::Result<Self> {
+        let client = options.client_builder()?.build()?;
+        let redirect_client = options
+            .client_builder()?
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        Ok(Self {
+            client,
+            redirect_client,
+            endpoints: options.endpoints.clone(),
+            protocol: options.protocol,
+            resolved_protocols: RwLock::new(HashMap::new()),
+            region: RwLock::new(Region::default()),
+            clock_offset: AtomicI64::new(0),
+        })
+    }
+
+    /// 复用连接池和配置创建一个新的实例，区域等状态相互独立
+    pub fn fork(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            redirect_client: self.redirect_client.clone(),
+            endpoints: self.endpoints.clone(),
+            protocol: self.protocol,
+            resolved_protocols: RwLock::new(self.resolved_protocols.read().unwrap().clone()),
+            region: RwLock::new(Region::default()),
+            clock_offset: AtomicI64::new(self.clock_offset()),
+        }
+    }
+
+    pub async fn login(&self, username: &str, password: &str) -> anyhow::Result<LoginOutcome> {
         let signature = self.fetch_signature().await?;
         let login_resp = self
-            .fetch_login_response(username, password, &signature)
+            .fetch_login_response(username, password, &signature, None)
             .await?;
+        self.complete_login(&login_resp, &signature).await
+    }
+
+    /// 带上用户输入的图形验证码重新提交登录表单
+    pub async fn login_with_captcha(
+        &self,
+        username: &str,
+        password: &str,
+        challenge: &CaptchaChallenge,
+        code: &str,
+    ) -> anyhow::Result<LoginOutcome> {
+        let login_resp = self
+            .fetch_login_response(
+                username,
+                password,
+                &challenge.signature,
+                Some((challenge, code)),
+            )
+            .await?;
+        self.complete_login(&login_resp, &challenge.signature).await
+    }
+
+    /// 触发短信或邮件验证码的发送
+    pub async fn send_verification_ticket(
+        &self,
+        challenge: &VerificationChallenge,
+        method: VerificationMethod,
+    ) -> anyhow::Result<()> {
+        let url = format!(
+            "{}{}/auth/send{}Ticket",
+            self.endpoints.account,
+            IDENTITY_PATH,
+            method.name()
+        );
+        let mut params = HashMap::new();
+        params.insert("retry", "0");
+        params.insert("icode", "");
+        params.insert("_json", "true");
+        let response = self
+            .client
+            .post(url)
+            .header("Cookie", identity_cookie(challenge))
+            .form(&params)
+            .send()
+            .await?;
+        let json = self.parse_json_from_response(response).await?;
+        let ticket_resp: IdentityTicketResponse = serde_json::from_str(&json)?;
+        if ticket_resp.code != 0 {
+            return Err(MikitError::Verification(ticket_resp.desc).into());
+        }
+        Ok(())
+    }
+
+    /// 提交验证码，验证通过后用通行证cookie重新走一次`serviceLogin`得到账号信息
+    pub async fn verify_login(
+        &self,
+        challenge: &VerificationChallenge,
+        method: VerificationMethod,
+        ticket: &str,
+    ) -> anyhow::Result<MiAccount> {
+        let flag = method.flag().to_string();
+        let url = Url::parse_with_params(
+            &format!(
+                "{}{}/auth/verify{}",
+                self.endpoints.account,
+                IDENTITY_PATH,
+                method.name()
+            ),
+            &[("_flag", flag.as_str()), ("_json", "true")],
+        )?;
+        let mut params = HashMap::new();
+        params.insert("_flag", flag.as_str());
+        params.insert("ticket", ticket);
+        params.insert("trust", "true");
+        params.insert("_json", "true");
+        let response = self
+            .redirect_client
+            .post(url)
+            .header("Cookie", identity_cookie(challenge))
+            .form(&params)
+            .send()
+            .await?;
+        let mut cookies = self.parse_cookies(response.headers());
+        let json = self.parse_json_from_response(response).await?;
+        let verify_resp: IdentityTicketResponse = serde_json::from_str(&json)?;
+        let location = match verify_resp.location {
+            Some(location) if verify_resp.code == 0 => location,
+            _ => return Err(MikitError::Verification(verify_resp.desc).into()),
+        };
+        self.follow_redirects(&location, &mut cookies).await?;
+        let login_resp = self.fetch_service_login(&cookies).await?;
+        if login_resp.code != 0 || login_resp.ssecurity.is_empty() {
+            return Err(MikitError::Login(login_resp.code, login_resp.desc).into());
+        }
         self.fetch_auth_device_info(&login_resp).await
     }
 
-    pub async fn execute_command<T: DeserializeOwned>(
+    /// 申请扫码登录的二维码
+    pub async fn fetch_qr_login(&self) -> anyhow::Result<QrLoginTicket> {
+        let dc = SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .as_millis()
+            .to_string();
+        let url = Url::parse_with_params(
+            &self.account_url(QR_LOGIN_PATH),
+            &[
+                ("_qrsize", "240"),
+                ("qs", "?sid=xiaomiio&_json=true"),
+                ("callback", STS_CALLBACK),
+                ("_hasLogo", "false"),
+                ("sid", "xiaomiio"),
+                ("serviceParam", ""),
+                ("_locale", "zh_CN"),
+                ("_dc", dc.as_str()),
+            ],
+        )?;
+        let response = self.client.get(url).send().await?;
+        let json = self.parse_json_from_response(response).await?;
+        let qr_resp: QrLoginResponse = serde_json::from_str(&json)?;
+        match qr_resp.ticket {
+            Some(ticket) if qr_resp.code == 0 => Ok(ticket),
+            _ => Err(MikitError::Login(qr_resp.code, qr_resp.desc).into()),
+        }
+    }
+
+    /// 下载二维码图片
+    pub async fn fetch_qr_image(&self, ticket: &QrLoginTicket) -> anyhow::Result<Vec<u8>> {
+        let response = self.client.get(&ticket.qr_image_url).send().await?;
+        Ok(response.error_for_status()?.bytes().await?.to_vec())
+    }
+
+    /// 获取spec服务器上所有已发布的型号及版本
+    pub async fn fetch_spec_instances(&self, base_url: &str) -> anyhow::Result<Vec<SpecInstance>> {
+        let instances = self
+            .client
+            .get(format!("{}/instances?status=released", base_url))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<SpecInstanceList>()
+            .await?;
+        Ok(instances.instances)
+    }
+
+    pub async fn fetch_spec(&self, base_url: &str, urn: &str) -> anyhow::Result<Value> {
+        let url = Url::parse_with_params(&format!("{}/instance", base_url), &[("type", urn)])?;
+        let spec = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+        Ok(spec)
+    }
+
+    /// 长轮询等待米家APP扫码确认，确认后换取账号信息
+    pub async fn wait_qr_login(&self, ticket: &QrLoginTicket) -> anyhow::Result<MiAccount> {
+        let deadline = Instant::now() + Duration::from_secs(ticket.timeout);
+        let mut retry_delay = QR_POLL_RETRY_DELAY;
+        while Instant::now() < deadline {
+            let response = match self
+                .client
+                .get(&ticket.poll_url)
+                .timeout(QR_POLL_TIMEOUT)
+                .send()
+                .await
+            {
+                Err(e) if e.is_timeout() => continue,
+                response => response?,
+            };
+            if !response.status().is_success() {
+                trace!("qr login poll status:{}", response.status());
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                tokio::time::sleep(retry_delay.min(remaining)).await;
+                retry_delay = (retry_delay * 2).min(QR_POLL_MAX_RETRY_DELAY);
+                continue;
+            }
+            let mut passport_cookies = self.parse_cookies(response.headers());
+            let json = self.parse_json_from_response(response).await?;
+            let mut login_resp: AccountLoginResponse = serde_json::from_str(&json)?;
+            if login_resp.code != 0 || login_resp.ssecurity.is_empty() {
+                return Err(MikitError::Login(login_resp.code, login_resp.desc).into());
+            }
+            if let Some(pass_token) = login_resp.pass_token.as_ref() {
+                passport_cookies.insert("passToken".to_string(), pass_token.to_string());
+            }
+            passport_cookies.insert("userId".to_string(), login_resp.user_id.to_string());
+            login_resp.passport_cookies = passport_cookies;
+            return self.fetch_auth_device_info(&login_resp).await;
+        }
+        Err(MikitError::Login(-1, "qr code expired".to_string()).into())
+    }
+
+    pub fn region(&self) -> Region {
+        *self.region.read().unwrap()
+    }
+
+    pub fn set_region(&self, region: Region) {
+        *self.region.write().unwrap() = region;
+    }
+
+    pub fn clock_offset(&self) -> i64 {
+        self.clock_offset.load(Ordering::Relaxed)
+    }
+
+    /// 根据记录的时钟偏差校正后的当前时间
+    fn server_time(&self) -> SystemTime {
+        let now = SystemTime::now();
+        let offset = self.clock_offset();
+        if offset >= 0 {
+            now + Duration::from_secs(offset as u64)
+        } else {
+            now - Duration::from_secs(offset.unsigned_abs())
+        }
+    }
+
+    /// 从响应的`Date`头中获取服务器时间并更新时钟偏差
+    fn update_clock_offset(&self, headers: &HeaderMap) {
+        let Some(server_time) = headers
+            .get(DATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| httpdate::parse_http_date(value).ok())
+        else {
+            return;
+        };
+        let offset = match server_time.duration_since(SystemTime::now()) {
+            Result::Ok(ahead) => ahead.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        };
+        self.clock_offset.store(offset, Ordering::Relaxed);
+    }
+
+    /// 使用账号中保存的`passToken`重新走`serviceLogin`，无需密码即可换取新的`serviceToken`
+    pub async fn refresh_session(&self, account: &MiAccount) -> anyhow::Result<MiAccount> {
+        let login_resp = self.fetch_service_login(&account.cookies).await?;
+        if login_resp.code != 0 || login_resp.ssecurity.is_empty() {
+            return Err(MikitError::UnLogin.into());
+        }
+        let mut refreshed = self.fetch_auth_device_info(&login_resp).await?;
+        refreshed.device_id = account.device_id.clone();
+        Ok(refreshed)
+    }
+
+    /// 在指定区域执行命令，设置了`Endpoints::command`时区域不影响请求地址
+    pub async fn execute_command_in_region<T: DeserializeOwned>(
         &self,
         command: CommandReqeust,
         account: &MiAccount,
+        region: Region,
     ) -> anyhow::Result<T> {
         let uri = command.get_uri();
         let data = command.get_data()?;
-        self.execute_command_uri_and_data::<T>(&uri, &data, account)
+        let base_url = match self.endpoints.command.as_ref() {
+            Some(command) => command.clone(),
+            None => command_api(region),
+        };
+        self.execute_command_uri_and_data::<T>(&base_url, &uri, &data, account)
             .await
     }
 
     async fn fetch_signature(&self) -> anyhow::Result<AccountSignatureResponse> {
-        let url = Url::parse_with_params(SIGNATURE_API, &[("sid", "xiaomiio"), ("_json", "true")])?;
+        let url = Url::parse_with_params(
+            &self.account_url(SIGNATURE_PATH),
+            &[("sid", "xiaomiio"), ("_json", "true")],
+        )?;
         let response = self.client.get(url).send().await?;
         let json = self.parse_json_from_response(response).await?;
         serde_json::from_str(&json).map_err(|e| MikitError::JsonParse(e).into())
     }
 
+    /// 带着通行证cookie请求`serviceLogin`，cookie有效时会直接返回`ssecurity`
+    async fn fetch_service_login(
+        &self,
+        cookies: &HashMap<String, String>,
+    ) -> anyhow::Result<AccountLoginResponse> {
+        let url = Url::parse_with_params(
+            &self.account_url(SIGNATURE_PATH),
+            &[("sid", "xiaomiio"), ("_json", "true")],
+        )?;
+        let cookie = format_cookies(
+            PASSPORT_COOKIES
+                .iter()
+                .filter_map(|name| cookies.get(*name).map(|value| (*name, value.as_str()))),
+        );
+        let response = self.client.get(url).header("Cookie", cookie).send().await?;
+        let mut passport_cookies: HashMap<String, String> = PASSPORT_COOKIES
+            .iter()
+            .filter_map(|name| cookies.get_key_value(*name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        passport_cookies.extend(self.parse_cookies(response.headers()));
+        let json = self.parse_json_from_response(response).await?;
+        let mut login_resp: AccountLoginResponse = serde_json::from_str(&json)?;
+        login_resp.passport_cookies = passport_cookies;
+        Ok(login_resp)
+    }
+
     async fn fetch_login_response(
         &self,
         username: &str,
         password: &str,
         signature: &AccountSignatureResponse,
+        captcha: Option<(&CaptchaChallenge, &str)>,
     ) -> anyhow::Result<AccountLoginResponse> {
         let hash = encrypt_with_md5(password).to_uppercase();
         let mut params = HashMap::new();
@@ -76,9 +467,136 @@ impl HttpClient {
         params.insert("_json", "true");
         params.insert("user", username);
         params.insert("hash", &hash);
-        let response = self.client.post(LOGIN_API).form(&params).send().await?;
+        let mut request = self.client.post(self.account_url(LOGIN_PATH));
+        if let Some((challenge, code)) = captcha {
+            params.insert("captCode", code);
+            request = request.header(
+                "Cookie",
+                format_cookies(
+                    challenge
+                        .cookies
+                        .iter()
+                        .map(|(name, value)| (name.as_str(), value.as_str())),
+                ),
+            );
+        }
+        let response = request.form(&params).send().await?;
+        let passport_cookies = self.parse_cookies(response.headers());
         let json = self.parse_json_from_response(response).await?;
-        serde_json::from_str(&json).map_err(|e| MikitError::JsonParse(e).into())
+        let mut login_resp: AccountLoginResponse = serde_json::from_str(&json)?;
+        login_resp.passport_cookies = passport_cookies;
+        Ok(login_resp)
+    }
+
+    async fn complete_login(
+        &self,
+        login_resp: &AccountLoginResponse,
+        signature: &AccountSignatureResponse,
+    ) -> anyhow::Result<LoginOutcome> {
+        if let Some(url) = login_resp
+            .captcha_url
+            .as_ref()
+            .filter(|url| !url.is_empty())
+        {
+            let challenge = self.fetch_captcha_challenge(url, signature).await?;
+            return Ok(LoginOutcome::CaptchaRequired(challenge));
+        }
+        if let Some(url) = login_resp
+            .notification_url
+            .as_ref()
+            .filter(|url| !url.is_empty())
+        {
+            let challenge = self.fetch_verification_challenge(url).await?;
+            return Ok(LoginOutcome::VerificationRequired(challenge));
+        }
+        if login_resp.code != 0 || login_resp.location.is_empty() {
+            return Err(MikitError::Login(login_resp.code, login_resp.desc.clone()).into());
+        }
+        let account = self.fetch_auth_device_info(login_resp).await?;
+        Ok(LoginOutcome::Success(account))
+    }
+
+    /// 下载验证码图片，并保留图片接口下发的`ick`等cookie
+    async fn fetch_captcha_challenge(
+        &self,
+        captcha_url: &str,
+        signature: &AccountSignatureResponse,
+    ) -> anyhow::Result<CaptchaChallenge> {
+        let url = Url::parse(&self.account_url(LOGIN_PATH))?.join(captcha_url)?;
+        let response = self.client.get(url).send().await?;
+        let cookies = self.parse_cookies(response.headers());
+        let image = response.bytes().await?.to_vec();
+        Ok(CaptchaChallenge {
+            image,
+            cookies,
+            signature: signature.clone(),
+        })
+    }
+
+    /// 打开二次验证页面，获取`identity_session`以及可用的验证方式
+    async fn fetch_verification_challenge(
+        &self,
+        notification_url: &str,
+    ) -> anyhow::Result<VerificationChallenge> {
+        let url = notification_url.replacen("authStart", "list", 1);
+        let response = self.client.get(url).send().await?;
+        let cookies = self.parse_cookies(response.headers());
+        let identity_session = cookies.get("identity_session").cloned().ok_or(
+            MikitError::Verification("can not find identity session".to_string()),
+        )?;
+        let json = self.parse_json_from_response(response).await?;
+        let list_resp: IdentityListResponse = serde_json::from_str(&json)?;
+        let mut flags = list_resp.options;
+        if flags.is_empty() {
+            flags.extend(list_resp.flag);
+        }
+        let methods: Vec<VerificationMethod> = flags
+            .into_iter()
+            .filter_map(VerificationMethod::from_flag)
+            .collect();
+        if methods.is_empty() {
+            return Err(
+                MikitError::Verification("no supported verification method".to_string()).into(),
+            );
+        }
+        Ok(VerificationChallenge {
+            notification_url: notification_url.to_string(),
+            identity_session,
+            methods,
+        })
+    }
+
+    /// 手动跟随重定向，收集每一跳下发的cookie
+    async fn follow_redirects(
+        &self,
+        location: &str,
+        cookies: &mut HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        let mut url = Url::parse(location)?;
+        for _ in 0..MAX_REDIRECTS {
+            let cookie = format_cookies(
+                cookies
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str())),
+            );
+            let response = self
+                .redirect_client
+                .get(url.clone())
+                .header("Cookie", cookie)
+                .send()
+                .await?;
+            cookies.extend(self.parse_cookies(response.headers()));
+            if !response.status().is_redirection() {
+                return Ok(());
+            }
+            let next = response
+                .headers()
+                .get("location")
+                .and_then(|value| value.to_str().ok())
+                .ok_or(MikitError::Unknown("redirect without location".to_string()))?;
+            url = url.join(next)?;
+        }
+        Err(MikitError::Unknown("too many redirects".to_string()).into())
     }
 
     async fn fetch_auth_device_info(
@@ -92,12 +610,14 @@ impl HttpClient {
             urlencoding::encode(encode_to_base64(&encrypt_with_sha1(&nonce)).as_str())
         );
         let response = self.client.get(url).send().await?;
-        let cookies = self.parse_cookies(response.headers());
-        if cookies.is_empty() {
+        let auth_cookies = self.parse_cookies(response.headers());
+        if auth_cookies.is_empty() {
             return Err(
                 MikitError::Unknown("can not find cookies in auth device api".to_string()).into(),
             );
         }
+        let mut cookies = login_resp.passport_cookies.clone();
+        cookies.extend(auth_cookies);
         Ok(MiAccount {
             user_id: login_resp.user_id.to_string(),
             security_token: login_resp.ssecurity.to_string(),
@@ -106,52 +626,152 @@ impl HttpClient {
                 .get("serviceToken")
                 .unwrap_or(&"".to_string())
                 .to_string(),
-            cookies: cookies,
+            cookies,
         })
     }
 
+    fn account_url(&self, path: &str) -> String {
+        format!("{}{}", self.endpoints.account, path)
+    }
+
     async fn parse_json_from_response(&self, response: Response) -> anyhow::Result<String> {
         let body = response.text().await?;
-        println!("network response text:{}", &body);
-        Ok(String::from(&body[11..]))
+        Ok(String::from(body.strip_prefix(JSON_PREFIX).unwrap_or(&body)))
     }
 
+    /// `ProtocolMode::Auto`下优先使用加密协议，只有服务端明确不支持时才改用明文协议重发，
+    /// 被拒绝的请求没有执行，写入等命令重发也是安全的。结果按地址和接口记录，
+    /// 之后直接使用可用的协议
     async fn execute_command_uri_and_data<T: DeserializeOwned>(
         &self,
+        base_url: &str,
+        uri: &str,
+        data: &str,
+        account: &MiAccount,
+    ) -> anyhow::Result<T> {
+        let url = format!("{}{}", base_url, uri);
+        let protocol = match self.protocol {
+            ProtocolMode::Auto => self.resolved_protocol(&url).unwrap_or(ProtocolMode::Auto),
+            protocol => protocol,
+        };
+        match protocol {
+            ProtocolMode::Plain => {
+                self.execute_plain_command(base_url, uri, data, account)
+                    .await
+            }
+            ProtocolMode::Encrypted => {
+                self.execute_encrypted_command(base_url, uri, data, account)
+                    .await
+            }
+            ProtocolMode::Auto => {
+                match self
+                    .execute_encrypted_command(base_url, uri, data, account)
+                    .await
+                {
+                    Err(e) if MikitError::is_encryption_rejected(&e) => {
+                        self.resolve_protocol(&url, ProtocolMode::Plain);
+                        trace!("encrypted command rejected, fallback to plain:{}", e);
+                        self.execute_plain_command(base_url, uri, data, account)
+                            .await
+                    }
+                    result => {
+                        if result.is_ok() {
+                            self.resolve_protocol(&url, ProtocolMode::Encrypted);
+                        }
+                        result
+                    }
+                }
+            }
+        }
+    }
+
+    fn resolved_protocol(&self, url: &str) -> Option<ProtocolMode> {
+        self.resolved_protocols.read().unwrap().get(url).copied()
+    }
+
+    fn resolve_protocol(&self, url: &str, protocol: ProtocolMode) {
+        self.resolved_protocols
+            .write()
+            .unwrap()
+            .insert(url.to_string(), protocol);
+    }
+
+    async fn execute_plain_command<T: DeserializeOwned>(
+        &self,
+        base_url: &str,
         uri: &str,
         data: &str,
         account: &MiAccount,
     ) -> anyhow::Result<T> {
-        let nonce = generate_nonce();
+        let nonce = generate_nonce(self.server_time());
         let signed_nonce = generate_signed_nonce(&account.security_token, &nonce);
         let signature = generate_command_signature(uri, &signed_nonce, &nonce, data);
-        let url = format!("{}{}", COMMAND_API, uri);
-        let cookie = format!(
-            "PassportDeviceId={};userId={};serviceToken={};",
-            account.device_id.as_str(),
-            account.user_id.as_str(),
-            account.service_token
-        );
-        let mut headers = HeaderMap::new();
-        headers.insert("Cookie", HeaderValue::from_str(&cookie)?);
-        headers.insert(
-            "x-xiaomi-protocal-flag-cli",
-            HeaderValue::from_str("PROTOCAL-HTTP2")?,
-        );
+        let url = format!("{}{}", base_url, uri);
         let mut params: HashMap<&str, &str> = HashMap::new();
         params.insert("_nonce", &nonce);
         params.insert("data", data);
         params.insert("signature", &signature);
-        self.client
+        let response = self
+            .client
             .post(url)
             .form(&params)
-            .headers(headers)
+            .headers(command_headers(account)?)
             .send()
             .await
-            .map_err(|e| MikitError::Network(e))?
-            .json::<T>()
+            .map_err(MikitError::Network)?;
+        let status = response.status();
+        self.update_clock_offset(response.headers());
+        let body = response.text().await.map_err(MikitError::Network)?;
+        parse_command_response(status, &body)
+    }
+
+    /// 加密协议：`data`和`rc4_hash__`使用RC4加密，签名基于加密后的参数计算，响应同样需要解密
+    async fn execute_encrypted_command<T: DeserializeOwned>(
+        &self,
+        base_url: &str,
+        uri: &str,
+        data: &str,
+        account: &MiAccount,
+    ) -> anyhow::Result<T> {
+        let nonce = generate_nonce(self.server_time());
+        let signed_nonce = generate_signed_nonce(&account.security_token, &nonce);
+        let rc4_hash = generate_encrypted_signature("POST", uri, &signed_nonce, &[("data", data)]);
+        let encrypted_data = encrypt_with_rc4(&signed_nonce, data.as_bytes());
+        let encrypted_hash = encrypt_with_rc4(&signed_nonce, rc4_hash.as_bytes());
+        let signature = generate_encrypted_signature(
+            "POST",
+            uri,
+            &signed_nonce,
+            &[("data", &encrypted_data), ("rc4_hash__", &encrypted_hash)],
+        );
+        let params = [
+            ("data", encrypted_data.as_str()),
+            ("rc4_hash__", encrypted_hash.as_str()),
+            ("signature", signature.as_str()),
+            ("ssecurity", account.security_token.as_str()),
+            ("_nonce", nonce.as_str()),
+        ];
+        let mut headers = command_headers(account)?;
+        headers.insert(
+            "MIOT-ENCRYPT-ALGORITHM",
+            HeaderValue::from_str("ENCRYPT-RC4")?,
+        );
+        headers.insert("Accept-Encoding", HeaderValue::from_str("identity")?);
+        let response = self
+            .client
+            .post(format!("{}{}", base_url, uri))
+            .form(&params)
+            .headers(headers)
+            .send()
             .await
-            .map_err(|e| MikitError::Network(e).into())
+            .map_err(MikitError::Network)?;
+        let status = response.status();
+        self.update_clock_offset(response.headers());
+        if ENCRYPTION_REJECTED_STATUS.contains(&status) {
+            return Err(MikitError::EncryptionRejected(status.as_u16()).into());
+        }
+        let body = response.text().await.map_err(MikitError::Network)?;
+        parse_command_response(status, &decrypt_command_body(&signed_nonce, body))
     }
 
     fn parse_cookies(&self, header_map: &HeaderMap) -> HashMap<String, String> {
@@ -162,24 +782,95 @@ impl HttpClient {
                 trace!("can not parse headers cause empty cookies");
                 continue;
             }
-            String::from_utf8(value.as_bytes().to_vec())
+            // 只取`name=value`部分，忽略Path、Expires等属性
+            if let Some((name, value)) = String::from_utf8(value.as_bytes().to_vec())
                 .unwrap_or("".to_string())
                 .trim()
-                .split(";")
-                .filter(|x| x.contains("="))
-                .map(|x| x.split_once("=").unwrap_or((&"", &"")))
-                .for_each(|x| {
-                    result.insert(x.0.to_string(), x.1.to_string());
-                });
+                .split(';')
+                .next()
+                .and_then(|x| x.split_once('='))
+            {
+                result.insert(name.trim().to_string(), value.trim().to_string());
+            }
         }
         result
     }
 }
 
+/// 登录态过期时接口会返回401，或者返回`message`为`auth err`的json
+fn parse_command_response<T: DeserializeOwned>(
+    status: StatusCode,
+    body: &str,
+) -> anyhow::Result<T> {
+    if status == StatusCode::UNAUTHORIZED {
+        return Err(MikitError::AuthExpired.into());
+    }
+    let value: Value = serde_json::from_str(body)?;
+    if value.get("message").and_then(Value::as_str) == Some("auth err") {
+        return Err(MikitError::AuthExpired.into());
+    }
+    serde_json::from_value(value).map_err(|e| MikitError::JsonParse(e).into())
+}
+
+/// 大陆区域没有前缀，其他区域为`{region}.api.io.mi.com`
+fn command_api(region: Region) -> String {
+    match region {
+        Region::Cn => format!("https://{}/app", COMMAND_HOST),
+        _ => format!("https://{}.{}/app", region.code(), COMMAND_HOST),
+    }
+}
+
+fn command_headers(account: &MiAccount) -> anyhow::Result<HeaderMap> {
+    let cookie = format!(
+        "PassportDeviceId={};userId={};serviceToken={};",
+        account.device_id.as_str(),
+        account.user_id.as_str(),
+        account.service_token
+    );
+    let mut headers = HeaderMap::new();
+    headers.insert("Cookie", HeaderValue::from_str(&cookie)?);
+    headers.insert(
+        "x-xiaomi-protocal-flag-cli",
+        HeaderValue::from_str("PROTOCAL-HTTP2")?,
+    );
+    Ok(headers)
+}
+
+/// 加密协议的响应是base64编码的RC4密文，出错时服务端可能直接返回明文json
+fn decrypt_command_body(signed_nonce: &str, body: String) -> String {
+    if serde_json::from_str::<Value>(&body).is_ok_and(|value| value.is_object()) {
+        return body;
+    }
+    decrypt_with_rc4(signed_nonce, &body)
+        .and_then(|decrypted| String::from_utf8(decrypted).ok())
+        .unwrap_or(body)
+}
+
+fn format_cookies<'a>(cookies: impl Iterator<Item = (&'a str, &'a str)>) -> String {
+    cookies
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+fn identity_cookie(challenge: &VerificationChallenge) -> String {
+    format!("identity_session={}", challenge.identity_session)
+}
+
+#[derive(Clone)]
 pub enum CommandReqeust {
     DeviceList,
     GetProperties(DevicePropertiesRequestParams),
     SetProperties(DevicePropertiesRequestParams),
+    Action(ActionRequestParams),
+    HomeList,
+    SceneList(String),
+    RunScene(String),
+    DeviceData(DeviceDataRequestParams),
+    Rpc(RpcRequestParams),
+    Consumables(ConsumableRequestParams),
+    Statistics(StatisticsRequestParams),
+    Raw(String, Value),
 }
 
 impl CommandReqeust {
@@ -196,6 +887,38 @@ impl CommandReqeust {
             CommandReqeust::SetProperties(params) => {
                 serde_json::to_string_pretty(params).map_err(|e| e.into())
             }
+            CommandReqeust::Action(params) => {
+                serde_json::to_string_pretty(params).map_err(|e| e.into())
+            }
+            CommandReqeust::HomeList => Ok(r#"{
+                    "fg":true,
+                    "fetch_share":true,
+                    "fetch_share_dev":true,
+                    "limit":300,
+                    "app_ver":7
+                }"#
+            .to_string()),
+            CommandReqeust::SceneList(home_id) => {
+                Ok(serde_json::json!({ "home_id": home_id }).to_string())
+            }
+            CommandReqeust::RunScene(scene_id) => Ok(serde_json::json!({
+                "scene_id": scene_id,
+                "trigger_key": "user.click",
+            })
+            .to_string()),
+            CommandReqeust::DeviceData(params) => {
+                serde_json::to_string_pretty(params).map_err(|e| e.into())
+            }
+            CommandReqeust::Rpc(params) => {
+                serde_json::to_string_pretty(params).map_err(|e| e.into())
+            }
+            CommandReqeust::Consumables(params) => {
+                serde_json::to_string_pretty(params).map_err(|e| e.into())
+            }
+            CommandReqeust::Statistics(params) => {
+                serde_json::to_string_pretty(params).map_err(|e| e.into())
+            }
+            CommandReqeust::Raw(_, data) => Ok(data.to_string()),
         }
     }
 
@@ -204,6 +927,484 @@ impl CommandReqeust {
             CommandReqeust::DeviceList => "/home/device_list".to_string(),
             CommandReqeust::GetProperties(_) => "/miotspec/prop/get".to_string(),
             CommandReqeust::SetProperties(_) => "/miotspec/prop/set".to_string(),
+            CommandReqeust::Action(_) => "/miotspec/action".to_string(),
+            CommandReqeust::HomeList => "/v2/homeroom/gethome".to_string(),
+            CommandReqeust::SceneList(_) => {
+                "/appgateway/miot/appsceneservice/AppSceneService/GetSceneList".to_string()
+            }
+            CommandReqeust::RunScene(_) => {
+                "/appgateway/miot/appsceneservice/AppSceneService/RunScene".to_string()
+            }
+            CommandReqeust::DeviceData(_) => "/user/get_user_device_data".to_string(),
+            CommandReqeust::Rpc(params) => format!("/home/rpc/{}", params.did),
+            CommandReqeust::Consumables(_) => "/v2/home/standard_consumable_items".to_string(),
+            CommandReqeust::Statistics(_) => "/v2/user/statistics".to_string(),
+            CommandReqeust::Raw(uri, _) if uri.starts_with('/') => uri.clone(),
+            CommandReqeust::Raw(uri, _) => format!("/{}", uri),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant, SystemTime};
+
+    use reqwest::StatusCode;
+    use serde_json::Value;
+
+    use super::{
+        decrypt_command_body, parse_command_response, CommandReqeust, Endpoints, HttpClient,
+        HttpOptions,
+    };
+    use crate::models::{
+        CommandResponse, DeviceProperties, DevicePropertiesRequestParams, LoginOutcome, MiAccount,
+        MikitError, ProtocolMode, Region, VerificationMethod,
+    };
+    use crate::stub_server::{spawn_request_stub_server, spawn_stub_server, StubResponse};
+    use crate::utils::encrypt_with_rc4;
+
+    fn test_account() -> MiAccount {
+        MiAccount {
+            user_id: "1".to_string(),
+            security_token: "c2VjdXJpdHk=".to_string(),
+            device_id: "device".to_string(),
+            service_token: "token".to_string(),
+            cookies: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_qr_login() {
+        let poll_calls = Arc::new(AtomicUsize::new(0));
+        let server_poll_calls = poll_calls.clone();
+        let base = spawn_stub_server(move |path, base| {
+            if path.starts_with("/longPolling/loginUrl") {
+                StubResponse::json(format!(
+                    r#"{{"code":0,"qr":"{0}/qr.png","loginUrl":"{0}/login","lp":"{0}/lp","timeout":10}}"#,
+                    base
+                ))
+            } else if path.starts_with("/lp")
+                && server_poll_calls.fetch_add(1, Ordering::SeqCst) < 2
+            {
+                StubResponse::text(503, "busy")
+            } else if path.starts_with("/lp") {
+                StubResponse::json(format!(
+                    r#"{{"code":0,"desc":"成功","location":"{}/sts?d=1","nonce":123,"ssecurity":"c2VjdXJpdHk=","userId":42,"passToken":"pass"}}"#,
+                    base
+                ))
+            } else if path.starts_with("/sts") {
+                StubResponse {
+                    status: 200,
+                    headers: vec![("set-cookie", "serviceToken=token; Path=/".to_string())],
+                    body: b"ok".to_vec(),
+                }
+            } else {
+                StubResponse {
+                    status: 200,
+                    headers: vec![],
+                    body: vec![0x89, 0x50, 0x4e, 0x47],
+                }
+            }
+        })
+        .await;
+        let client = HttpClient::new(&HttpOptions {
+            endpoints: Endpoints {
+                account: base.clone(),
+                command: None,
+            },
+            ..Default::default()
+        })
+        .unwrap();
+        let ticket = client.fetch_qr_login().await.unwrap();
+        assert_eq!(format!("{}/qr.png", base), ticket.qr_image_url);
+        assert_eq!(10, ticket.timeout);
+        let image = client.fetch_qr_image(&ticket).await.unwrap();
+        assert_eq!(vec![0x89, 0x50, 0x4e, 0x47], image);
+
+        let started = Instant::now();
+        let account = client.wait_qr_login(&ticket).await.unwrap();
+        assert_eq!(3, poll_calls.load(Ordering::SeqCst));
+        assert!(started.elapsed() >= Duration::from_secs(3));
+        assert_eq!("42", account.user_id);
+        assert_eq!("c2VjdXJpdHk=", account.security_token);
+        assert_eq!("token", account.service_token);
+        assert_eq!(Some(&"pass".to_string()), account.cookies.get("passToken"));
+        assert!(!account.cookies.contains_key("Path"));
+    }
+
+    #[tokio::test]
+    async fn test_identity_verification() {
+        let base = spawn_request_stub_server(|request, base| {
+            let cookie = request.header("cookie").unwrap_or_default();
+            let path = request.path.as_str();
+            if path.starts_with("/pass/serviceLoginAuth2") {
+                StubResponse::json(format!(
+                    r#"{{"code":0,"notificationUrl":"{}/identity/authStart?sid=xiaomiio"}}"#,
+                    base
+                ))
+            } else if path.starts_with("/pass/serviceLogin") && cookie.contains("passToken=pass") {
+                StubResponse::json(format!(
+                    r#"{{"code":0,"location":"{}/sts?d=1","nonce":1,"ssecurity":"c2VjdXJpdHk=","userId":42}}"#,
+                    base
+                ))
+            } else if path.starts_with("/pass/serviceLogin") {
+                StubResponse::json(
+                    r#"{"qs":"%3Fsid%3Dxiaomiio","_sign":"sign","sid":"xiaomiio","callback":"cb"}"#
+                        .to_string(),
+                )
+            } else if path.starts_with("/identity/list") {
+                let mut response = StubResponse::json(r#"{"code":0,"options":[4,8]}"#.to_string());
+                response
+                    .headers
+                    .push(("set-cookie", "identity_session=session; Path=/".to_string()));
+                response
+            } else if !cookie.contains("identity_session=session") && path.starts_with("/identity")
+            {
+                StubResponse::json(r#"{"code":70016,"desc":"no session"}"#.to_string())
+            } else if path.starts_with("/identity/auth/sendPhoneTicket") {
+                StubResponse::json(r#"{"code":0}"#.to_string())
+            } else if path.starts_with("/identity/auth/verifyPhone") {
+                if request.form("ticket").as_deref() != Some("123456") {
+                    return StubResponse::json(r#"{"code":70014,"tips":"wrong ticket"}"#.to_string());
+                }
+                StubResponse::json(format!(r#"{{"code":0,"location":"{}/redirect/1"}}"#, base))
+            } else if path.starts_with("/redirect/1") {
+                let mut response = StubResponse::redirect("/redirect/2");
+                response
+                    .headers
+                    .push(("set-cookie", "passToken=pass; Path=/".to_string()));
+                response
+            } else if path.starts_with("/redirect/2") && cookie.contains("passToken=pass") {
+                let mut response = StubResponse::text(200, "ok");
+                response
+                    .headers
+                    .push(("set-cookie", "userId=42; Path=/".to_string()));
+                response
+            } else if path.starts_with("/sts") {
+                let mut response = StubResponse::text(200, "ok");
+                response
+                    .headers
+                    .push(("set-cookie", "serviceToken=token; Path=/".to_string()));
+                response
+            } else {
+                StubResponse::text(404, "not found")
+            }
+        })
+        .await;
+        let client = HttpClient::new(&HttpOptions {
+            endpoints: Endpoints {
+                account: base,
+                command: None,
+            },
+            ..Default::default()
+        })
+        .unwrap();
+        let challenge = match client.login("user", "password").await.unwrap() {
+            LoginOutcome::VerificationRequired(challenge) => challenge,
+            outcome => panic!("unexpected outcome:{:?}", outcome),
+        };
+        assert_eq!("session", challenge.identity_session);
+        assert_eq!(
+            vec![VerificationMethod::Phone, VerificationMethod::Email],
+            challenge.methods
+        );
+        client
+            .send_verification_ticket(&challenge, VerificationMethod::Phone)
+            .await
+            .unwrap();
+
+        let error = client
+            .verify_login(&challenge, VerificationMethod::Phone, "000000")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::Verification(desc)) if desc == "wrong ticket"
+        ));
+        let account = client
+            .verify_login(&challenge, VerificationMethod::Phone, "123456")
+            .await
+            .unwrap();
+        assert_eq!("42", account.user_id);
+        assert_eq!("token", account.service_token);
+        assert_eq!(Some(&"pass".to_string()), account.cookies.get("passToken"));
+    }
+
+    #[tokio::test]
+    async fn test_captcha_login() {
+        let base = spawn_request_stub_server(|request, base| {
+            let path = request.path.as_str();
+            if path.starts_with("/pass/serviceLoginAuth2") {
+                let solved = request.form("captCode").as_deref() == Some("abcd")
+                    && request.header("cookie") == Some("ick=ick");
+                if solved {
+                    StubResponse::json(format!(
+                        r#"{{"code":0,"location":"{}/sts?d=1","nonce":1,"ssecurity":"c2VjdXJpdHk=","userId":42}}"#,
+                        base
+                    ))
+                } else {
+                    StubResponse::json(
+                        r#"{"code":87001,"captchaUrl":"/pass/getCode?icodeType=login"}"#
+                            .to_string(),
+                    )
+                }
+            } else if path.starts_with("/pass/serviceLogin") {
+                StubResponse::json(
+                    r#"{"qs":"%3Fsid%3Dxiaomiio","_sign":"sign","sid":"xiaomiio","callback":"cb"}"#
+                        .to_string(),
+                )
+            } else if path.starts_with("/pass/getCode") {
+                StubResponse {
+                    status: 200,
+                    headers: vec![("set-cookie", "ick=ick; Path=/".to_string())],
+                    body: vec![0xff, 0xd8],
+                }
+            } else if path.starts_with("/sts") {
+                let mut response = StubResponse::text(200, "ok");
+                response
+                    .headers
+                    .push(("set-cookie", "serviceToken=token; Path=/".to_string()));
+                response
+            } else {
+                StubResponse::text(404, "not found")
+            }
+        })
+        .await;
+        let client = HttpClient::new(&HttpOptions {
+            endpoints: Endpoints {
+                account: base,
+                command: None,
+            },
+            ..Default::default()
+        })
+        .unwrap();
+        let challenge = match client.login("user", "password").await.unwrap() {
+            LoginOutcome::CaptchaRequired(challenge) => challenge,
+            outcome => panic!("unexpected outcome:{:?}", outcome),
+        };
+        assert_eq!(vec![0xff, 0xd8], challenge.image);
+        assert_eq!(Some(&"ick".to_string()), challenge.cookies.get("ick"));
+        assert_eq!("sign", challenge.signature.sign);
+
+        let outcome = client
+            .login_with_captcha("user", "password", &challenge, "wxyz")
+            .await
+            .unwrap();
+        assert!(matches!(outcome, LoginOutcome::CaptchaRequired(_)));
+        let outcome = client
+            .login_with_captcha("user", "password", &challenge, "abcd")
+            .await
+            .unwrap();
+        let LoginOutcome::Success(account) = outcome else {
+            panic!("unexpected outcome:{:?}", outcome);
+        };
+        assert_eq!("token", account.service_token);
+    }
+
+    #[test]
+    fn test_auth_expired_response() {
+        let result =
+            parse_command_response::<CommandResponse<Value>>(StatusCode::UNAUTHORIZED, "auth err");
+        assert!(MikitError::is_auth_expired(&result.unwrap_err()));
+
+        let result = parse_command_response::<CommandResponse<Value>>(
+            StatusCode::OK,
+            r#"{"code":3,"message":"auth err"}"#,
+        );
+        assert!(MikitError::is_auth_expired(&result.unwrap_err()));
+    }
+
+    #[test]
+    fn test_command_response() {
+        let response = parse_command_response::<CommandResponse<Value>>(
+            StatusCode::OK,
+            r#"{"code":0,"message":"ok","result":{"list":[]}}"#,
+        )
+        .unwrap();
+        assert_eq!(0, response.code);
+        assert!(response.result.is_some());
+    }
+
+    #[test]
+    fn test_decrypt_command_body() {
+        let body = r#"{"code":0,"message":"ok","result":null}"#;
+        let encrypted = encrypt_with_rc4("dGVzdGtleQ==", body.as_bytes());
+        assert_eq!(body, decrypt_command_body("dGVzdGtleQ==", encrypted));
+        assert_eq!(body, decrypt_command_body("dGVzdGtleQ==", body.to_string()));
+    }
+
+    fn auto_client(base: String) -> HttpClient {
+        HttpClient::new(&HttpOptions {
+            endpoints: Endpoints {
+                account: base.clone(),
+                command: Some(base),
+            },
+            protocol: ProtocolMode::Auto,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_protocol_fallback() {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let server_requests = requests.clone();
+        let base = spawn_request_stub_server(move |request, _| {
+            let encrypted = request.form("rc4_hash__").is_some();
+            server_requests
+                .lock()
+                .unwrap()
+                .push((request.path.clone(), encrypted));
+            if encrypted {
+                StubResponse::text(501, "encryption not supported")
+            } else {
+                StubResponse::text(200, r#"{"code":0,"message":"ok","result":[]}"#)
+            }
+        })
+        .await;
+        let client = auto_client(base);
+        let account = test_account();
+        let response = client
+            .execute_command_in_region::<CommandResponse<Value>>(
+                CommandReqeust::DeviceList,
+                &account,
+                Region::Cn,
+            )
+            .await
+            .unwrap();
+        assert_eq!(0, response.code);
+        client
+            .execute_command_in_region::<CommandResponse<Value>>(
+                CommandReqeust::DeviceList,
+                &account,
+                Region::Cn,
+            )
+            .await
+            .unwrap();
+        let set_properties = CommandReqeust::SetProperties(DevicePropertiesRequestParams {
+            params: vec![DeviceProperties::new_set_properties("1", 2, 1, true)],
+        });
+        for _ in 0..2 {
+            client
+                .execute_command_in_region::<CommandResponse<Value>>(
+                    set_properties.clone(),
+                    &account,
+                    Region::Cn,
+                )
+                .await
+                .unwrap();
+        }
+        let requests = requests.lock().unwrap().clone();
+        let expected: Vec<(String, bool)> = [
+            ("/home/device_list", true),
+            ("/home/device_list", false),
+            ("/home/device_list", false),
+            ("/miotspec/prop/set", true),
+            ("/miotspec/prop/set", false),
+            ("/miotspec/prop/set", false),
+        ]
+        .iter()
+        .map(|(path, encrypted)| (path.to_string(), *encrypted))
+        .collect();
+        assert_eq!(expected, requests);
+    }
+
+    #[tokio::test]
+    async fn test_protocol_resolved_per_base_url() {
+        let encrypted_requests = Arc::new(AtomicUsize::new(0));
+        let plain_base = spawn_request_stub_server(|request, _| {
+            if request.form("rc4_hash__").is_some() {
+                StubResponse::text(501, "encryption not supported")
+            } else {
+                StubResponse::text(200, r#"{"code":0,"message":"ok","result":[]}"#)
+            }
+        })
+        .await;
+        let server_requests = encrypted_requests.clone();
+        let encrypted_base = spawn_request_stub_server(move |request, _| {
+            if request.form("rc4_hash__").is_some() {
+                server_requests.fetch_add(1, Ordering::SeqCst);
+            }
+            StubResponse::text(200, r#"{"code":0,"message":"ok","result":[]}"#)
+        })
+        .await;
+        let client = auto_client(plain_base.clone());
+        let account = test_account();
+        for base in [&plain_base, &encrypted_base] {
+            client
+                .execute_command_uri_and_data::<CommandResponse<Value>>(
+                    base,
+                    "/home/device_list",
+                    "{}",
+                    &account,
+                )
+                .await
+                .unwrap();
+        }
+        assert_eq!(1, encrypted_requests.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_write_not_replayed() {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let server_requests = requests.clone();
+        let base = spawn_request_stub_server(move |request, _| {
+            server_requests
+                .lock()
+                .unwrap()
+                .push(request.form("rc4_hash__").is_some());
+            StubResponse::text(400, "bad request")
+        })
+        .await;
+        let client = auto_client(base);
+        let set_properties = CommandReqeust::SetProperties(DevicePropertiesRequestParams {
+            params: vec![DeviceProperties::new_set_properties("1", 2, 1, true)],
+        });
+        for _ in 0..2 {
+            let error = client
+                .execute_command_in_region::<CommandResponse<Value>>(
+                    set_properties.clone(),
+                    &test_account(),
+                    Region::Cn,
+                )
+                .await
+                .unwrap_err();
+            assert!(!MikitError::is_encryption_rejected(&error));
+        }
+        // 普通的错误状态不会改用明文协议，也不会重发
+        assert_eq!(vec![true, true], *requests.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_clock_offset() {
+        let server_time = SystemTime::now() + Duration::from_secs(7200);
+        let base = spawn_stub_server(move |_, _| StubResponse {
+            status: 200,
+            headers: vec![("date", httpdate::fmt_http_date(server_time))],
+            body: br#"{"code":0,"message":"ok","result":{"list":[]}}"#.to_vec(),
+        })
+        .await;
+        let client = HttpClient::new(&HttpOptions {
+            endpoints: Endpoints {
+                account: base.clone(),
+                command: Some(base),
+            },
+            protocol: ProtocolMode::Plain,
+            ..Default::default()
+        })
+        .unwrap();
+        client
+            .execute_command_in_region::<CommandResponse<Value>>(
+                CommandReqeust::DeviceList,
+                &test_account(),
+                Region::Cn,
+            )
+            .await
+            .unwrap();
+        assert!((7198..=7200).contains(&client.clock_offset()));
+        assert!((7198..=7200).contains(&client.fork().clock_offset()));
+    }
+}
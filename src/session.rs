@@ -0,0 +1,115 @@
+use anyhow::Ok;
+
+use crate::models::{MikitError, SessionBundle};
+use crate::utils::{
+    decode_to_base64_vec, decrypt_with_aes_gcm, derive_key, encode_to_base64, encrypt_with_aes_gcm,
+    get_random_bytes, KDF_ITERATIONS,
+};
+
+pub static SESSION_VERSION: u32 = 1;
+static SESSION_PREFIX: &str = "mikit-session";
+const PLAIN_MODE: &str = "plain";
+const ENCRYPTED_MODE: &str = "enc";
+static SALT_LEN: usize = 16;
+
+/// 将会话编码为`mikit-session.v{version}.{plain|enc}.{base64}`格式的字符串，
+/// 提供口令时内容使用AES-256-GCM加密，盐值放在密文前面
+pub fn encode_session(bundle: &SessionBundle, passphrase: Option<&str>) -> anyhow::Result<String> {
+    let json = serde_json::to_vec(bundle)?;
+    let (mode, payload) = match passphrase {
+        Some(passphrase) => {
+            let salt = get_random_bytes(SALT_LEN);
+            let key = derive_key(passphrase, &salt, KDF_ITERATIONS);
+            let encrypted = encrypt_with_aes_gcm(&key, &json);
+            (ENCRYPTED_MODE, [salt, encrypted].concat())
+        }
+        None => (PLAIN_MODE, json),
+    };
+    Ok(format!(
+        "{}.v{}.{}.{}",
+        SESSION_PREFIX,
+        bundle.version,
+        mode,
+        encode_to_base64(&payload)
+    ))
+}
+
+pub fn decode_session(token: &str, passphrase: Option<&str>) -> anyhow::Result<SessionBundle> {
+    let parts: Vec<&str> = token.trim().split('.').collect();
+    let [prefix, version, mode, payload] = parts[..] else {
+        return Err(MikitError::Session("malformed session token".to_string()).into());
+    };
+    if prefix != SESSION_PREFIX {
+        return Err(MikitError::Session("malformed session token".to_string()).into());
+    }
+    if version != format!("v{}", SESSION_VERSION) {
+        return Err(MikitError::Session(format!("unsupported session version:{}", version)).into());
+    }
+    let payload = decode_to_base64_vec(payload);
+    let json = match (mode, passphrase) {
+        (PLAIN_MODE, _) => payload,
+        (ENCRYPTED_MODE, Some(passphrase)) if payload.len() > SALT_LEN => {
+            let (salt, encrypted) = payload.split_at(SALT_LEN);
+            let key = derive_key(passphrase, salt, KDF_ITERATIONS);
+            decrypt_with_aes_gcm(&key, encrypted).ok_or(MikitError::Session(
+                "wrong passphrase or corrupted session".to_string(),
+            ))?
+        }
+        (ENCRYPTED_MODE, None) => {
+            return Err(MikitError::Session("session requires a passphrase".to_string()).into())
+        }
+        _ => return Err(MikitError::Session("malformed session token".to_string()).into()),
+    };
+    let bundle: SessionBundle = serde_json::from_slice(&json)?;
+    Ok(bundle)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{decode_session, encode_session, SESSION_VERSION};
+    use crate::models::{MiAccount, Region, SessionBundle};
+
+    fn bundle() -> SessionBundle {
+        SessionBundle {
+            version: SESSION_VERSION,
+            account: MiAccount {
+                user_id: "42".to_string(),
+                security_token: "security".to_string(),
+                device_id: "device".to_string(),
+                service_token: "token".to_string(),
+                cookies: HashMap::new(),
+            },
+            region: Region::De,
+            device_id: "device".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_plain_session() {
+        let token = encode_session(&bundle(), None).unwrap();
+        assert!(token.starts_with("mikit-session.v1.plain."));
+        let decoded = decode_session(&token, None).unwrap();
+        assert_eq!("token", decoded.account.service_token);
+        assert_eq!(Region::De, decoded.region);
+    }
+
+    #[test]
+    fn test_encrypted_session() {
+        let token = encode_session(&bundle(), Some("passphrase")).unwrap();
+        assert!(token.starts_with("mikit-session.v1.enc."));
+        assert!(decode_session(&token, None).is_err());
+        assert!(decode_session(&token, Some("wrong")).is_err());
+        let decoded = decode_session(&token, Some("passphrase")).unwrap();
+        assert_eq!("42", decoded.account.user_id);
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let token = encode_session(&bundle(), None)
+            .unwrap()
+            .replacen(".v1.", ".v9.", 1);
+        assert!(decode_session(&token, None).is_err());
+    }
+}
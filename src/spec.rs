@@ -0,0 +1,722 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Ok;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::models::{MikitError, MiotValue, ModelInfo, ModelProperty, Unit};
+
+pub static SPEC_API: &str = "https://miot-spec.org/miot-spec-v2";
+
+/// MIoT spec的来源
+#[derive(Clone, Debug)]
+pub enum SpecSource {
+    /// spec服务器地址，默认为`SPEC_API`
+    Server(String),
+    /// 本地文件或目录：目录下按`{model}.json`存放，文件为`{model: spec}`格式的合集
+    Local(PathBuf),
+}
+
+impl Default for SpecSource {
+    fn default() -> Self {
+        SpecSource::Server(SPEC_API.to_string())
+    }
+}
+
+/// spec的来源及属性校验配置
+#[derive(Clone, Debug)]
+pub(crate) struct SpecOptions {
+    pub source: SpecSource,
+    pub validate: bool,
+    /// 不做属性校验的型号
+    pub skip_validation: HashSet<String>,
+}
+
+impl Default for SpecOptions {
+    fn default() -> Self {
+        Self {
+            source: SpecSource::default(),
+            validate: true,
+            skip_validation: HashSet::new(),
+        }
+    }
+}
+
+impl SpecOptions {
+    /// 是否需要校验该型号的属性
+    pub fn validates(&self, model: &str) -> bool {
+        self.validate && !self.skip_validation.contains(model)
+    }
+}
+
+/// 从本地文件或目录读取spec
+pub(crate) fn read_local_spec(path: &Path, model: &str) -> anyhow::Result<DeviceSpec> {
+    if path.is_dir() {
+        let json = fs::read_to_string(path.join(format!("{}.json", model)))
+            .map_err(|_| spec_not_found(model))?;
+        return DeviceSpec::parse(model, &json);
+    }
+    let json = fs::read_to_string(path)
+        .map_err(|e| MikitError::Spec(format!("{}:{}", path.display(), e)))?;
+    let mut bundle: HashMap<String, Value> = serde_json::from_str(&json)?;
+    let spec = bundle.remove(model).ok_or_else(|| spec_not_found(model))?;
+    DeviceSpec::from_value(model, spec)
+}
+
+pub(crate) fn spec_not_found(model: &str) -> anyhow::Error {
+    MikitError::SpecNotFound(model.to_string()).into()
+}
+
+/// 从urn中取出名称，例如`urn:miot-spec-v2:property:brightness:0000000D:yeelink-color1:1`为`brightness`
+fn urn_name(urn: &str) -> &str {
+    urn.split(':').nth(3).unwrap_or(urn)
+}
+
+/// 一个设备型号的MIoT spec
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeviceSpec {
+    #[serde(default)]
+    pub model: String,
+    #[serde(rename = "type")]
+    pub urn: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub services: Vec<SpecService>,
+}
+
+impl DeviceSpec {
+    pub fn parse(model: &str, json: &str) -> anyhow::Result<DeviceSpec> {
+        DeviceSpec::from_value(model, serde_json::from_str(json)?)
+    }
+
+    pub fn from_value(model: &str, value: Value) -> anyhow::Result<DeviceSpec> {
+        let mut spec: DeviceSpec = serde_json::from_value(value)?;
+        spec.model = model.to_string();
+        Ok(spec)
+    }
+
+    pub fn service(&self, name: &str) -> Option<&SpecService> {
+        self.services.iter().find(|service| service.name() == name)
+    }
+
+    /// 按`服务名.属性名`查找属性，例如`light.brightness`，返回siid和属性
+    pub fn property(&self, name: &str) -> Option<(usize, &SpecProperty)> {
+        let (service, property) = name.split_once('.')?;
+        let service = self.service(service)?;
+        service
+            .property(property)
+            .map(|property| (service.iid, property))
+    }
+
+    pub fn property_by_iid(&self, siid: usize, piid: usize) -> Option<&SpecProperty> {
+        self.services
+            .iter()
+            .find(|service| service.iid == siid)?
+            .properties
+            .iter()
+            .find(|property| property.iid == piid)
+    }
+
+    /// 按`服务名.动作名`查找动作，返回siid和动作
+    pub fn action(&self, name: &str) -> Option<(usize, &SpecAction)> {
+        let (service, action) = name.split_once('.')?;
+        let service = self.service(service)?;
+        service
+            .actions
+            .iter()
+            .find(|item| item.name() == action)
+            .map(|action| (service.iid, action))
+    }
+
+    pub fn model_info(&self) -> ModelInfo {
+        let props = self
+            .services
+            .iter()
+            .flat_map(|service| {
+                service.properties.iter().map(|property| ModelProperty {
+                    name: format!("{}.{}", service.name(), property.name()),
+                    siid: service.iid.to_string(),
+                    piid: property.iid.to_string(),
+                    value: Value::Null,
+                })
+            })
+            .collect();
+        ModelInfo {
+            name: self.description.clone(),
+            model: self.model.clone(),
+            props,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SpecService {
+    pub iid: usize,
+    #[serde(rename = "type")]
+    pub urn: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub properties: Vec<SpecProperty>,
+    #[serde(default)]
+    pub actions: Vec<SpecAction>,
+    #[serde(default)]
+    pub events: Vec<SpecEvent>,
+}
+
+impl SpecService {
+    pub fn name(&self) -> &str {
+        urn_name(&self.urn)
+    }
+
+    pub fn property(&self, name: &str) -> Option<&SpecProperty> {
+        self.properties
+            .iter()
+            .find(|property| property.name() == name)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SpecProperty {
+    pub iid: usize,
+    #[serde(rename = "type")]
+    pub urn: String,
+    #[serde(default)]
+    pub description: String,
+    pub format: PropertyFormat,
+    #[serde(default)]
+    pub access: Vec<String>,
+    pub unit: Option<String>,
+    #[serde(rename = "value-range")]
+    pub value_range: Option<ValueRange>,
+    #[serde(rename = "value-list", default)]
+    pub value_list: Vec<SpecValue>,
+}
+
+impl SpecProperty {
+    pub fn name(&self) -> &str {
+        urn_name(&self.urn)
+    }
+
+    pub fn readable(&self) -> bool {
+        self.access.iter().any(|access| access == "read")
+    }
+
+    pub fn writable(&self) -> bool {
+        self.access.iter().any(|access| access == "write")
+    }
+
+    pub fn notifiable(&self) -> bool {
+        self.access.iter().any(|access| access == "notify")
+    }
+
+    pub fn unit(&self) -> Option<Unit> {
+        self.unit.as_deref().and_then(Unit::parse)
+    }
+
+    /// 按spec的格式转换值，例如`"on"`转为`true`，百分比属性的`0.5`转为`50`，可选值的描述转为枚举
+    pub fn coerce(&self, value: MiotValue) -> Result<MiotValue, ValidationIssue> {
+        let mismatch = ValidationIssue::FormatMismatch(self.format);
+        if !self.value_list.is_empty() {
+            let item = match &value {
+                MiotValue::String(text) => self.value_list.iter().find(|item| {
+                    normalize(&item.description) == normalize(text)
+                        || text.parse::<i64>().is_ok_and(|number| number == item.value)
+                }),
+                value => {
+                    let number = value.as_i64().ok_or(mismatch)?;
+                    self.value_list.iter().find(|item| item.value == number)
+                }
+            };
+            return item
+                .map(|item| MiotValue::Enum {
+                    value: item.value,
+                    description: item.description.clone(),
+                })
+                .ok_or(ValidationIssue::NotInValueList);
+        }
+        match self.format {
+            PropertyFormat::Bool => match &value {
+                MiotValue::Bool(_) => Result::Ok(value),
+                MiotValue::String(text) => match text.to_lowercase().as_str() {
+                    "on" | "true" | "yes" | "1" => Result::Ok(MiotValue::Bool(true)),
+                    "off" | "false" | "no" | "0" => Result::Ok(MiotValue::Bool(false)),
+                    _ => Err(mismatch),
+                },
+                value => match value.as_i64() {
+                    Some(0) => Result::Ok(MiotValue::Bool(false)),
+                    Some(1) => Result::Ok(MiotValue::Bool(true)),
+                    _ => Err(mismatch),
+                },
+            },
+            PropertyFormat::Float => match &value {
+                MiotValue::String(text) => text
+                    .trim()
+                    .parse::<f64>()
+                    .map(MiotValue::Float)
+                    .map_err(|_| mismatch),
+                value => value.as_f64().map(MiotValue::Float).ok_or(mismatch),
+            },
+            PropertyFormat::String | PropertyFormat::Hex => match value {
+                MiotValue::String(_) => Result::Ok(value),
+                MiotValue::Enum { description, .. } => Result::Ok(MiotValue::String(description)),
+                value => Result::Ok(MiotValue::String(value.to_string())),
+            },
+            PropertyFormat::Unknown => Result::Ok(value),
+            format => {
+                let number = match &value {
+                    MiotValue::Bool(value) => Some(*value as i64),
+                    MiotValue::String(text) => text.trim().parse::<i64>().ok(),
+                    MiotValue::Float(number)
+                        if self.unit() == Some(Unit::Percentage)
+                            && (0.0..=1.0).contains(number) =>
+                    {
+                        Some((number * 100.0).round() as i64)
+                    }
+                    value => value.as_i64(),
+                };
+                number
+                    .and_then(|number| format.integer(number))
+                    .ok_or(mismatch)
+            }
+        }
+    }
+
+    /// 将设备上报的值按spec的格式转换，只做无损的转换，不符合spec时保留原始值
+    pub fn decode(&self, value: MiotValue) -> MiotValue {
+        let decoded = if !self.value_list.is_empty() {
+            value.as_i64().and_then(|number| {
+                self.value_list
+                    .iter()
+                    .find(|item| item.value == number)
+                    .map(|item| MiotValue::Enum {
+                        value: item.value,
+                        description: item.description.clone(),
+                    })
+            })
+        } else {
+            match (self.format, &value) {
+                (PropertyFormat::Bool, MiotValue::Bool(_))
+                | (PropertyFormat::String | PropertyFormat::Hex, MiotValue::String(_)) => None,
+                (PropertyFormat::Float, value) => value.as_f64().map(MiotValue::Float),
+                (format, value) => value.as_i64().and_then(|number| format.integer(number)),
+            }
+        };
+        decoded.unwrap_or(value)
+    }
+
+    /// 检查要写入的值是否符合spec的访问权限、格式、取值范围和可选值
+    pub fn validate(&self, value: &MiotValue) -> Result<(), ValidationIssue> {
+        if !self.writable() {
+            return Err(ValidationIssue::NotWritable);
+        }
+        let number = match self.format {
+            PropertyFormat::Bool if matches!(value, MiotValue::Bool(_)) => return Result::Ok(()),
+            PropertyFormat::String | PropertyFormat::Hex
+                if matches!(value, MiotValue::String(_)) =>
+            {
+                return Result::Ok(())
+            }
+            PropertyFormat::Unknown => return Result::Ok(()),
+            PropertyFormat::Bool | PropertyFormat::String | PropertyFormat::Hex => None,
+            PropertyFormat::Float => value.as_f64(),
+            format => value
+                .as_f64()
+                .filter(|number| number.fract() == 0.0)
+                .filter(|number| {
+                    format
+                        .integer_bounds()
+                        .is_none_or(|(min, max)| *number >= min && *number <= max)
+                }),
+        };
+        let Some(number) = number else {
+            return Err(ValidationIssue::FormatMismatch(self.format));
+        };
+        if let Some(range) = self.value_range {
+            if number < range.min || number > range.max {
+                return Err(ValidationIssue::OutOfRange(range));
+            }
+            let steps = (number - range.min) / range.step;
+            if range.step > 0.0 && (steps - steps.round()).abs() > 1e-6 {
+                return Err(ValidationIssue::InvalidStep(range));
+            }
+        }
+        if !self.value_list.is_empty()
+            && !self
+                .value_list
+                .iter()
+                .any(|item| item.value as f64 == number)
+        {
+            return Err(ValidationIssue::NotInValueList);
+        }
+        Result::Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PropertyFormat {
+    Bool,
+    Uint8,
+    Uint16,
+    Uint32,
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    Float,
+    String,
+    Hex,
+    #[serde(other)]
+    Unknown,
+}
+
+/// 比较可选值描述时忽略大小写、空格和连字符
+fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+impl PropertyFormat {
+    /// 转换为对应格式的整数值，超出范围时返回`None`
+    fn integer(&self, number: i64) -> Option<MiotValue> {
+        match self {
+            PropertyFormat::Uint8 => u8::try_from(number).ok().map(MiotValue::Uint8),
+            PropertyFormat::Uint16 => u16::try_from(number).ok().map(MiotValue::Uint16),
+            PropertyFormat::Uint32 => u32::try_from(number).ok().map(MiotValue::Uint32),
+            PropertyFormat::Int8 => i8::try_from(number).ok().map(MiotValue::Int8),
+            PropertyFormat::Int16 => i16::try_from(number).ok().map(MiotValue::Int16),
+            PropertyFormat::Int32 => i32::try_from(number).ok().map(MiotValue::Int32),
+            PropertyFormat::Int64 => Some(MiotValue::Int64(number)),
+            _ => None,
+        }
+    }
+
+    /// 整数类型的取值范围
+    fn integer_bounds(&self) -> Option<(f64, f64)> {
+        match self {
+            PropertyFormat::Uint8 => Some((0.0, u8::MAX as f64)),
+            PropertyFormat::Uint16 => Some((0.0, u16::MAX as f64)),
+            PropertyFormat::Uint32 => Some((0.0, u32::MAX as f64)),
+            PropertyFormat::Int8 => Some((i8::MIN as f64, i8::MAX as f64)),
+            PropertyFormat::Int16 => Some((i16::MIN as f64, i16::MAX as f64)),
+            PropertyFormat::Int32 => Some((i32::MIN as f64, i32::MAX as f64)),
+            PropertyFormat::Int64 => Some((i64::MIN as f64, i64::MAX as f64)),
+            _ => None,
+        }
+    }
+}
+
+/// spec中的`value-range`，格式为`[min, max, step]`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<f64>", into = "Vec<f64>")]
+pub struct ValueRange {
+    pub min: f64,
+    pub max: f64,
+    pub step: f64,
+}
+
+impl From<Vec<f64>> for ValueRange {
+    fn from(values: Vec<f64>) -> Self {
+        Self {
+            min: values.first().copied().unwrap_or(f64::MIN),
+            max: values.get(1).copied().unwrap_or(f64::MAX),
+            step: values.get(2).copied().unwrap_or(0.0),
+        }
+    }
+}
+
+impl From<ValueRange> for Vec<f64> {
+    fn from(range: ValueRange) -> Self {
+        vec![range.min, range.max, range.step]
+    }
+}
+
+/// spec中`value-list`的一项
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SpecValue {
+    pub value: i64,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SpecAction {
+    pub iid: usize,
+    #[serde(rename = "type")]
+    pub urn: String,
+    #[serde(default)]
+    pub description: String,
+    /// 输入参数对应的piid
+    #[serde(rename = "in", default)]
+    pub in_args: Vec<usize>,
+    /// 输出参数对应的piid
+    #[serde(default)]
+    pub out: Vec<usize>,
+}
+
+impl SpecAction {
+    pub fn name(&self) -> &str {
+        urn_name(&self.urn)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SpecEvent {
+    pub iid: usize,
+    #[serde(rename = "type")]
+    pub urn: String,
+    #[serde(default)]
+    pub description: String,
+    /// 事件参数对应的piid
+    #[serde(default)]
+    pub arguments: Vec<usize>,
+}
+
+impl SpecEvent {
+    pub fn name(&self) -> &str {
+        urn_name(&self.urn)
+    }
+}
+
+/// 属性不符合spec的原因
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidationIssue {
+    /// spec中没有该属性
+    UnknownProperty,
+    /// 只读属性
+    NotWritable,
+    /// 没有提供要写入的值
+    MissingValue,
+    FormatMismatch(PropertyFormat),
+    OutOfRange(ValueRange),
+    InvalidStep(ValueRange),
+    NotInValueList,
+}
+
+/// 单个属性的校验错误
+#[derive(Clone, Debug)]
+pub struct PropertyValidationError {
+    pub did: String,
+    pub siid: usize,
+    pub piid: usize,
+    pub issue: ValidationIssue,
+}
+
+/// spec服务器中已发布的实例
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct SpecInstanceList {
+    #[serde(default)]
+    pub instances: Vec<SpecInstance>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct SpecInstance {
+    pub model: String,
+    #[serde(default)]
+    pub version: u32,
+    #[serde(rename = "type")]
+    pub urn: String,
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::env;
+
+    use serde_json::json;
+
+    use super::{read_local_spec, DeviceSpec, PropertyFormat, ValidationIssue};
+    use crate::models::{DeviceProperties, MiotValue, Unit};
+    use crate::utils::get_random_string;
+
+    pub(crate) static LIGHT_SPEC: &str =
+        include_str!("../tests/fixtures/specs/yeelink.light.color1.json");
+
+    #[test]
+    fn test_parse_spec() {
+        let spec = DeviceSpec::parse("yeelink.light.color1", LIGHT_SPEC).unwrap();
+        assert_eq!("yeelink.light.color1", spec.model);
+        let (siid, brightness) = spec.property("light.brightness").unwrap();
+        assert_eq!((2, 2), (siid, brightness.iid));
+        assert_eq!(PropertyFormat::Uint8, brightness.format);
+        assert_eq!(Some(100.0), brightness.value_range.map(|range| range.max));
+        let (_, temperature) = spec.property("light.color-temperature").unwrap();
+        assert!(temperature.readable() && !temperature.writable());
+        assert_eq!(2, spec.property("light.mode").unwrap().1.value_list.len());
+        assert!(spec.property("light.missing").is_none());
+        assert_eq!(1, spec.action("light.toggle").unwrap().1.iid);
+
+        let info = spec.model_info();
+        assert_eq!(4, info.props.len());
+        assert_eq!("light.on", info.props[0].name);
+        assert_eq!(
+            ("2", "1"),
+            (info.props[0].siid.as_str(), info.props[0].piid.as_str())
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let spec = DeviceSpec::parse("yeelink.light.color1", LIGHT_SPEC).unwrap();
+        let property = |name| spec.property(name).unwrap().1;
+        assert_eq!(Ok(()), property("light.on").validate(&true.into()));
+        assert_eq!(
+            Err(ValidationIssue::FormatMismatch(PropertyFormat::Bool)),
+            property("light.on").validate(&"on".into())
+        );
+        assert!(property("light.on").validate(&1i64.into()).is_err());
+        assert_eq!(Ok(()), property("light.brightness").validate(&50u8.into()));
+        assert!(matches!(
+            property("light.brightness").validate(&101i64.into()),
+            Err(ValidationIssue::OutOfRange(_))
+        ));
+        assert_eq!(
+            Err(ValidationIssue::FormatMismatch(PropertyFormat::Uint8)),
+            property("light.brightness").validate(&50.5.into())
+        );
+        assert_eq!(Ok(()), property("light.mode").validate(&1i64.into()));
+        assert_eq!(
+            Err(ValidationIssue::NotInValueList),
+            property("light.mode").validate(&2i64.into())
+        );
+        assert_eq!(
+            Err(ValidationIssue::NotWritable),
+            property("light.color-temperature").validate(&4000u32.into())
+        );
+    }
+
+    #[test]
+    fn test_coerce() {
+        let spec = DeviceSpec::parse("yeelink.light.color1", LIGHT_SPEC).unwrap();
+        let property = |name| spec.property(name).unwrap().1;
+        assert_eq!(
+            Ok(MiotValue::Bool(true)),
+            property("light.on").coerce("on".into())
+        );
+        assert_eq!(
+            Ok(MiotValue::Bool(false)),
+            property("light.on").coerce(0i64.into())
+        );
+        assert_eq!(
+            Ok(MiotValue::Uint8(50)),
+            property("light.brightness").coerce(0.5.into())
+        );
+        assert_eq!(
+            Ok(MiotValue::Uint8(80)),
+            property("light.brightness").coerce("80".into())
+        );
+        assert_eq!(
+            Err(ValidationIssue::FormatMismatch(PropertyFormat::Uint8)),
+            property("light.brightness").coerce(300i64.into())
+        );
+        let night = MiotValue::Enum {
+            value: 1,
+            description: "Night".to_string(),
+        };
+        assert_eq!(
+            Ok(night.clone()),
+            property("light.mode").coerce("night".into())
+        );
+        assert_eq!(Ok(night.clone()), property("light.mode").coerce(1u8.into()));
+        assert_eq!(
+            Err(ValidationIssue::NotInValueList),
+            property("light.mode").coerce("Reading".into())
+        );
+        assert_eq!(json!(1), night.to_json());
+        assert_eq!(
+            Some(Unit::Kelvin),
+            property("light.color-temperature").unit()
+        );
+    }
+
+    #[test]
+    fn test_decode() {
+        let spec = DeviceSpec::parse("yeelink.light.color1", LIGHT_SPEC).unwrap();
+        let property = |name| spec.property(name).unwrap().1;
+        assert_eq!(
+            MiotValue::Uint32(4000),
+            property("light.color-temperature").decode(MiotValue::Int64(4000))
+        );
+        assert_eq!(
+            MiotValue::Enum {
+                value: 1,
+                description: "Night".to_string(),
+            },
+            property("light.mode").decode(MiotValue::Int64(1))
+        );
+        // 不符合spec的值原样保留，不做写入时的宽松转换
+        for value in [
+            MiotValue::Int64(256),
+            MiotValue::Float(0.5),
+            MiotValue::String("80".to_string()),
+        ] {
+            assert_eq!(value, property("light.brightness").decode(value.clone()));
+        }
+        for value in [MiotValue::String("on".to_string()), MiotValue::Int64(1)] {
+            assert_eq!(value, property("light.on").decode(value.clone()));
+        }
+        assert_eq!(
+            MiotValue::Int64(2),
+            property("light.mode").decode(MiotValue::Int64(2))
+        );
+    }
+
+    #[test]
+    fn test_miot_value_json() {
+        for json in [
+            json!(true),
+            json!(-3),
+            json!(1.5),
+            json!("text"),
+            json!(u64::MAX),
+            json!([1, "a"]),
+            json!({"x": 1}),
+        ] {
+            let value: MiotValue = serde_json::from_value(json.clone()).unwrap();
+            assert_eq!(json, serde_json::to_value(&value).unwrap());
+        }
+        assert_eq!(MiotValue::Json(json!([1])), MiotValue::from(json!([1])));
+        let properties: Vec<DeviceProperties> = serde_json::from_value(json!([
+            {"did": "1", "siid": 2, "piid": 1, "value": [1, 2], "code": 0},
+            {"did": "1", "siid": 2, "piid": 2, "value": 80, "code": 0},
+        ]))
+        .unwrap();
+        assert_eq!(Some(MiotValue::Json(json!([1, 2]))), properties[0].value);
+        assert_eq!(Some(MiotValue::Int64(80)), properties[1].value);
+        let property = DeviceProperties::new_set_properties("1", 2, 1, json!(true));
+        assert_eq!(Some(MiotValue::Bool(true)), property.value);
+        assert_eq!(
+            "Night",
+            MiotValue::Enum {
+                value: 1,
+                description: "Night".to_string()
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn test_local_source() {
+        let dir = env::temp_dir().join(format!("mikit_spec_{}", get_random_string(8)));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("yeelink.light.color1.json"), LIGHT_SPEC).unwrap();
+        let spec = read_local_spec(&dir, "yeelink.light.color1").unwrap();
+        assert_eq!(1, spec.services.len());
+        assert!(read_local_spec(&dir, "unknown.model").is_err());
+
+        let bundle = dir.join("bundle.json");
+        std::fs::write(
+            &bundle,
+            format!(r#"{{"yeelink.light.color1": {}}}"#, LIGHT_SPEC),
+        )
+        .unwrap();
+        let spec = read_local_spec(&bundle, "yeelink.light.color1").unwrap();
+        assert_eq!("yeelink.light.color1", spec.model);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,14 +1,33 @@
-use std::{path::Path, sync::Arc};
+use std::{fs, path::Path, sync::Arc};
 
 use anyhow::Ok;
 use directories::ProjectDirs;
 use serde::{de::DeserializeOwned, Serialize};
-use sled::Db;
+use sled::transaction::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionError,
+    TransactionalTree,
+};
+use sled::{Db, Tree};
 
-use crate::models::MikitError;
+use crate::models::{MikitError, StoreKey};
+use crate::utils::{
+    decode_to_base64_vec, decrypt_with_aes_gcm, derive_key, encrypt_with_aes_gcm, get_random_bytes,
+    KDF_ITERATIONS,
+};
 
+static SALT_KEY: &str = "store_salt";
+static KEY_CHECK_KEY: &str = "store_key_check";
+static KEY_CHECK_VALUE: &[u8] = b"mikit";
+/// 加密数据的前缀，用于区分旧版本写入的明文数据
+static ENCRYPTED_MAGIC: &[u8] = b"MKE1";
+
+pub(crate) type TransactionResult<T> = ConflictableTransactionResult<T, MikitError>;
+
+/// 基于sled的存储，每个实例对应一个命名空间（sled tree），默认为根命名空间
 pub struct DataSore {
     db: Arc<Db>,
+    tree: Tree,
+    cipher_key: Option<Arc<Vec<u8>>>,
 }
 
 impl DataSore {
@@ -18,43 +37,257 @@ impl DataSore {
             Some(dirs) => dirs.data_dir(),
             None => Path::new("."),
         };
-        let db_path = parent_dir.join("mikit_db");
+        DataSore::open(parent_dir.join("mikit_db"))
+    }
+
+    /// 打开指定目录下的数据库
+    pub(crate) fn open(db_path: impl AsRef<Path>) -> anyhow::Result<DataSore> {
         let sled = sled::open(db_path)?;
-        Ok(Self { db: Arc::new(sled) })
+        let tree = (*sled).clone();
+        Ok(Self {
+            db: Arc::new(sled),
+            tree,
+            cipher_key: None,
+        })
+    }
+
+    /// 开启加密存储，密钥错误时返回`MikitError::InvalidStoreKey`
+    pub(crate) fn unlock(&mut self, key: &StoreKey) -> anyhow::Result<()> {
+        let cipher_key = match key {
+            StoreKey::Passphrase(passphrase) => {
+                let salt = match self.db.get(SALT_KEY)? {
+                    Some(salt) => salt.to_vec(),
+                    None => {
+                        let salt = get_random_bytes(16);
+                        self.db.insert(SALT_KEY, salt.clone())?;
+                        salt
+                    }
+                };
+                derive_key(passphrase, &salt, KDF_ITERATIONS)
+            }
+            StoreKey::KeyFile(path) => read_key_file(path)?,
+        };
+        match self.db.get(KEY_CHECK_KEY)? {
+            Some(check) => {
+                if decrypt_with_aes_gcm(&cipher_key, &check).as_deref() != Some(KEY_CHECK_VALUE) {
+                    return Err(MikitError::InvalidStoreKey.into());
+                }
+            }
+            None => {
+                self.db.insert(
+                    KEY_CHECK_KEY,
+                    encrypt_with_aes_gcm(&cipher_key, KEY_CHECK_VALUE),
+                )?;
+            }
+        }
+        self.cipher_key = Some(Arc::new(cipher_key));
+        Ok(())
+    }
+
+    /// 打开同一数据库下的命名空间，不同命名空间的key互不影响
+    pub(crate) fn scoped(&self, namespace: &str) -> anyhow::Result<DataSore> {
+        let tree = self.db.open_tree(scope_name(namespace))?;
+        Ok(Self {
+            db: self.db.clone(),
+            tree,
+            cipher_key: self.cipher_key.clone(),
+        })
+    }
+
+    /// 删除整个命名空间及其数据
+    pub(crate) fn drop_scope(&self, namespace: &str) -> anyhow::Result<bool> {
+        self.db
+            .drop_tree(scope_name(namespace))
+            .map_err(|e| e.into())
     }
 
     pub fn set<T: Serialize>(&self, key: &str, data: &T) -> anyhow::Result<()> {
-        let db = self.db.clone();
         let mut serializer = rmp_serde::Serializer::new(Vec::new()).with_struct_map();
         data.serialize(&mut serializer)?;
-        db.insert(key, serializer.into_inner())?;
+        self.tree.insert(key, serializer.into_inner())?;
         Ok(())
     }
 
     pub fn get<T: DeserializeOwned>(&self, key: &str) -> anyhow::Result<T> {
-        let db = self.db.clone();
-        let bytes = db
+        let bytes = self
+            .tree
             .get(key)?
             .ok_or(MikitError::Unknown("none value!".to_string()))?;
         let value = rmp_serde::from_slice::<T>(&bytes)?;
         Ok(value)
     }
 
+    /// 开启加密存储时加密保存，否则与`set`相同
+    pub fn set_secret<T: Serialize>(&self, key: &str, data: &T) -> anyhow::Result<()> {
+        let mut serializer = rmp_serde::Serializer::new(Vec::new()).with_struct_map();
+        data.serialize(&mut serializer)?;
+        let bytes = serializer.into_inner();
+        match self.cipher_key.as_ref() {
+            Some(cipher_key) => {
+                let encrypted =
+                    [ENCRYPTED_MAGIC, &encrypt_with_aes_gcm(cipher_key, &bytes)].concat();
+                self.tree.insert(key, encrypted)?
+            }
+            None => self.tree.insert(key, bytes)?,
+        };
+        Ok(())
+    }
+
+    /// 读取`set_secret`保存的数据，开启加密存储后读到的旧明文数据会被自动加密回写
+    pub fn get_secret<T: Serialize + DeserializeOwned>(&self, key: &str) -> anyhow::Result<T> {
+        let bytes = self
+            .tree
+            .get(key)?
+            .ok_or(MikitError::Unknown("none value!".to_string()))?;
+        match (
+            bytes.strip_prefix(ENCRYPTED_MAGIC),
+            self.cipher_key.as_ref(),
+        ) {
+            (Some(encrypted), Some(cipher_key)) => {
+                let bytes = decrypt_with_aes_gcm(cipher_key, encrypted)
+                    .ok_or(MikitError::InvalidStoreKey)?;
+                Ok(rmp_serde::from_slice::<T>(&bytes)?)
+            }
+            (Some(_), None) => Err(MikitError::StoreKeyRequired.into()),
+            (None, cipher_key) => {
+                let value = rmp_serde::from_slice::<T>(&bytes)?;
+                if cipher_key.is_some() {
+                    self.set_secret(key, &value)?;
+                }
+                Ok(value)
+            }
+        }
+    }
+
+    /// 在事务中读写多个key，其他实例并发修改时`f`会被重新执行
+    pub(crate) fn transaction<R>(
+        &self,
+        f: impl Fn(&StoreTransaction) -> TransactionResult<R>,
+    ) -> anyhow::Result<R> {
+        self.tree
+            .transaction(|tree| f(&StoreTransaction { tree }))
+            .map_err(|e| match e {
+                TransactionError::Abort(e) => e.into(),
+                TransactionError::Storage(e) => MikitError::Store(e).into(),
+            })
+    }
+
+    pub fn contains(&self, key: &str) -> anyhow::Result<bool> {
+        self.tree.contains_key(key).map_err(|e| e.into())
+    }
+
+    pub fn remove(&self, key: &str) -> anyhow::Result<()> {
+        self.tree.remove(key)?;
+        Ok(())
+    }
+
     pub fn clear(&self) -> anyhow::Result<()> {
-        let db = self.db.clone();
-        db.clear().map_err(|e| e.into())
+        self.tree.clear().map_err(|e| e.into())
+    }
+}
+
+/// `DataSore::transaction`中使用的读写接口
+pub(crate) struct StoreTransaction<'a> {
+    tree: &'a TransactionalTree,
+}
+
+impl StoreTransaction<'_> {
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> TransactionResult<Option<T>> {
+        match self.tree.get(key)? {
+            Some(bytes) => rmp_serde::from_slice::<T>(&bytes)
+                .map(Some)
+                .map_err(|e| abort(MikitError::Unknown(e.to_string()))),
+            None => Result::Ok(None),
+        }
+    }
+
+    pub fn set<T: Serialize>(&self, key: &str, data: &T) -> TransactionResult<()> {
+        let mut serializer = rmp_serde::Serializer::new(Vec::new()).with_struct_map();
+        data.serialize(&mut serializer)
+            .map_err(|e| abort(MikitError::Unknown(e.to_string())))?;
+        self.tree.insert(key, serializer.into_inner())?;
+        Result::Ok(())
     }
 }
 
+/// 放弃事务，`DataSore::transaction`返回该错误
+pub(crate) fn abort(error: MikitError) -> ConflictableTransactionError<MikitError> {
+    ConflictableTransactionError::Abort(error)
+}
+
+fn read_key_file(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let content = fs::read(path)?;
+    if content.len() == 32 {
+        return Ok(content);
+    }
+    let key = decode_to_base64_vec(String::from_utf8_lossy(&content).trim());
+    if key.len() != 32 {
+        return Err(MikitError::InvalidStoreKey.into());
+    }
+    Ok(key)
+}
+
+fn scope_name(namespace: &str) -> String {
+    format!("scope/{}", namespace)
+}
+
 #[cfg(test)]
 mod test {
     use super::DataSore;
+    use crate::models::{MikitError, StoreKey};
 
     #[test]
     fn test() {
-        let mut store = DataSore::new("mikit", "com.nickming.test").unwrap();
+        let store = DataSore::new("mikit", "com.nickming.test").unwrap();
         store.set::<String>("test", &"test".to_string()).unwrap();
         assert_eq!(store.get::<String>("test").unwrap(), "test");
         store.clear().unwrap();
     }
+
+    #[test]
+    fn test_scoped() {
+        let store = DataSore::new("mikit_scoped", "com.nickming.test").unwrap();
+        let first = store.scoped("first").unwrap();
+        let second = store.scoped("second").unwrap();
+        first.set::<String>("key", &"first".to_string()).unwrap();
+        second.set::<String>("key", &"second".to_string()).unwrap();
+        assert_eq!(first.get::<String>("key").unwrap(), "first");
+        assert_eq!(second.get::<String>("key").unwrap(), "second");
+        assert!(!store.contains("key").unwrap());
+
+        first.clear().unwrap();
+        assert!(!first.contains("key").unwrap());
+        assert!(second.contains("key").unwrap());
+        assert!(store.drop_scope("second").unwrap());
+        assert!(!store.scoped("second").unwrap().contains("key").unwrap());
+        store.drop_scope("first").unwrap();
+    }
+
+    #[test]
+    fn test_secret() {
+        let plain = DataSore::new("mikit_secret", "com.nickming.test").unwrap();
+        plain
+            .set_secret::<String>("secret", &"plain".to_string())
+            .unwrap();
+        drop(plain);
+
+        let mut store = DataSore::new("mikit_secret", "com.nickming.test").unwrap();
+        store
+            .unlock(&StoreKey::Passphrase("passphrase".to_string()))
+            .unwrap();
+        assert_eq!(store.get_secret::<String>("secret").unwrap(), "plain");
+        assert!(store.get::<String>("secret").is_err());
+        drop(store);
+
+        let mut store = DataSore::new("mikit_secret", "com.nickming.test").unwrap();
+        let error = store
+            .unlock(&StoreKey::Passphrase("wrong".to_string()))
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MikitError>(),
+            Some(MikitError::InvalidStoreKey)
+        ));
+        assert!(store.get_secret::<String>("secret").is_err());
+        store.clear().unwrap();
+    }
 }
@@ -0,0 +1,141 @@
+//! 测试用的本地http服务，用来模拟小米账号和米家云服务
+
+use std::sync::Arc;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+pub struct StubResponse {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+impl StubResponse {
+    pub fn json(body: String) -> Self {
+        Self {
+            status: 200,
+            headers: vec![],
+            body: format!("&&&START&&&{}", body).into_bytes(),
+        }
+    }
+
+    /// 米家命令接口返回的是不带前缀的内容
+    pub fn text(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn redirect(location: &str) -> Self {
+        Self {
+            status: 302,
+            headers: vec![("location", location.to_string())],
+            body: vec![],
+        }
+    }
+}
+
+/// 模拟服务收到的请求
+pub struct StubRequest {
+    pub path: String,
+    /// 请求行和请求头
+    pub head: String,
+    pub body: String,
+}
+
+impl StubRequest {
+    /// 请求头的值，名称不区分大小写
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.head.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+
+    /// 表单请求体中的参数
+    pub fn form(&self, name: &str) -> Option<String> {
+        self.body.split('&').find_map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            (key == name).then(|| {
+                urlencoding::decode(&value.replace('+', " "))
+                    .map(|value| value.into_owned())
+                    .unwrap_or_default()
+            })
+        })
+    }
+}
+
+/// 启动一个本地的模拟服务，`handler`根据请求路径返回响应，返回服务的地址
+pub async fn spawn_stub_server<F>(handler: F) -> String
+where
+    F: Fn(&str, &str) -> StubResponse + Send + Sync + 'static,
+{
+    spawn_request_stub_server(move |request, base| handler(&request.path, base)).await
+}
+
+/// 与`spawn_stub_server`相同，`handler`可以拿到完整的请求
+pub async fn spawn_request_stub_server<F>(handler: F) -> String
+where
+    F: Fn(&StubRequest, &str) -> StubResponse + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let handler = Arc::new(handler);
+    let server_base = base.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let handler = handler.clone();
+            let base = server_base.clone();
+            tokio::spawn(async move {
+                let mut request = vec![];
+                let mut buf = [0; 4096];
+                let head_len = loop {
+                    if let Some(idx) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break idx + 4;
+                    }
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                };
+                let head = String::from_utf8_lossy(&request[..head_len]).to_string();
+                let mut body = request.split_off(head_len);
+                let mut request = StubRequest {
+                    path: head.split_whitespace().nth(1).unwrap_or("/").to_string(),
+                    head,
+                    body: String::new(),
+                };
+                let content_length = request
+                    .header("content-length")
+                    .and_then(|value| value.parse::<usize>().ok())
+                    .unwrap_or(0);
+                while body.len() < content_length {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    body.extend_from_slice(&buf[..n]);
+                }
+                request.body = String::from_utf8_lossy(&body).to_string();
+                let response = handler(&request, &base);
+                let mut head = format!(
+                    "HTTP/1.1 {} OK\r\ncontent-length: {}\r\nconnection: close\r\n",
+                    response.status,
+                    response.body.len()
+                );
+                for (name, value) in response.headers {
+                    head.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                head.push_str("\r\n");
+                stream.write_all(head.as_bytes()).await.unwrap();
+                stream.write_all(&response.body).await.unwrap();
+            });
+        }
+    });
+    base
+}
@@ -1,9 +1,21 @@
-use std::iter::repeat;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::aes::KeySize;
+use crypto::aes_gcm::AesGcm;
+use crypto::pbkdf2::pbkdf2;
+use crypto::rc4::Rc4;
+use crypto::symmetriccipher::SynchronousStreamCipher;
 use crypto::{digest::Digest, hmac::Hmac, mac::Mac, md5, sha1::Sha1, sha2::Sha256};
-use rand::Rng;
+use rand::{Rng, RngCore};
 
 static RANDOM_STR: &str = "1234567890abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+static AES_GCM_NONCE_LEN: usize = 12;
+static AES_GCM_TAG_LEN: usize = 16;
+/// RC4加密前丢弃的密钥流字节数
+static RC4_DROP_LEN: usize = 1024;
+/// 从口令派生密钥时PBKDF2的迭代次数
+pub static KDF_ITERATIONS: u32 = 100_000;
 
 /// 使用md5加密字符串
 pub fn encrypt_with_md5(content: &str) -> String {
@@ -42,9 +54,12 @@ pub fn get_random_string(count: usize) -> String {
     str
 }
 
-/// 获取nonce
-pub fn generate_nonce() -> String {
-    get_random_string(16)
+/// 获取nonce，8字节随机数加上4字节大端序的unix时间（分钟），再进行base64编码
+pub fn generate_nonce(now: SystemTime) -> String {
+    let minutes = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 60;
+    let mut bytes = get_random_bytes(8);
+    bytes.extend_from_slice(&(minutes as u32).to_be_bytes());
+    encode_to_base64(&bytes)
 }
 
 /// 获取签名的nonce
@@ -68,20 +83,102 @@ pub fn generate_command_signature(
     let mut hmac = Hmac::new(Sha256::new(), &decode_to_base64_vec(signed_nonce));
     hmac.input(sign.as_bytes());
     let result = hmac.result();
-    let code = result.code().clone();
+    let code = result.code();
     encode_to_base64(code)
 }
 
+/// 获取加密协议的签名，对`METHOD&uri&k=v...&signed_nonce`做sha1后base64编码
+pub fn generate_encrypted_signature(
+    method: &str,
+    uri: &str,
+    signed_nonce: &str,
+    params: &[(&str, &str)],
+) -> String {
+    let mut items = vec![method.to_uppercase(), uri.to_string()];
+    items.extend(
+        params
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value)),
+    );
+    items.push(signed_nonce.to_string());
+    encode_to_base64(&encrypt_with_sha1(&items.join("&")))
+}
+
+/// 使用signed nonce作为密钥进行RC4加密，返回base64编码的密文
+pub fn encrypt_with_rc4(signed_nonce: &str, payload: &[u8]) -> String {
+    encode_to_base64(&process_rc4(signed_nonce, payload))
+}
+
+/// 解密base64编码的RC4密文
+pub fn decrypt_with_rc4(signed_nonce: &str, payload: &str) -> Option<Vec<u8>> {
+    let payload = base64::decode(payload.trim()).ok()?;
+    Some(process_rc4(signed_nonce, &payload))
+}
+
+fn process_rc4(signed_nonce: &str, payload: &[u8]) -> Vec<u8> {
+    let mut rc4 = Rc4::new(&decode_to_base64_vec(signed_nonce));
+    let mut dropped = vec![0; RC4_DROP_LEN];
+    rc4.process(&vec![0; RC4_DROP_LEN], &mut dropped);
+    let mut out = vec![0; payload.len()];
+    rc4.process(payload, &mut out);
+    out
+}
+
+/// 获取长度为count的随机字节
+pub fn get_random_bytes(count: usize) -> Vec<u8> {
+    let mut bytes = vec![0; count];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+/// 使用PBKDF2-HMAC-SHA256从口令派生32字节的密钥
+pub fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> Vec<u8> {
+    let mut hmac = Hmac::new(Sha256::new(), passphrase.as_bytes());
+    let mut out = vec![0; 32];
+    pbkdf2(&mut hmac, salt, iterations, &mut out);
+    out
+}
+
+/// 使用AES-256-GCM加密，返回`nonce(12字节)+密文+tag(16字节)`
+pub fn encrypt_with_aes_gcm(key: &[u8], content: &[u8]) -> Vec<u8> {
+    let nonce = get_random_bytes(AES_GCM_NONCE_LEN);
+    let mut cipher = AesGcm::new(KeySize::KeySize256, key, &nonce, &[]);
+    let mut out = vec![0; content.len()];
+    let mut tag = vec![0; AES_GCM_TAG_LEN];
+    cipher.encrypt(content, &mut out, &mut tag);
+    [nonce, out, tag].concat()
+}
+
+/// 解密`encrypt_with_aes_gcm`的结果，密钥错误或数据被篡改时返回None
+pub fn decrypt_with_aes_gcm(key: &[u8], content: &[u8]) -> Option<Vec<u8>> {
+    if content.len() < AES_GCM_NONCE_LEN + AES_GCM_TAG_LEN {
+        return None;
+    }
+    let (nonce, rest) = content.split_at(AES_GCM_NONCE_LEN);
+    let (data, tag) = rest.split_at(rest.len() - AES_GCM_TAG_LEN);
+    let mut cipher = AesGcm::new(KeySize::KeySize256, key, nonce, &[]);
+    let mut out = vec![0; data.len()];
+    if cipher.decrypt(data, &mut out, tag) {
+        Some(out)
+    } else {
+        None
+    }
+}
+
 /// 获取一个指定长度的vec
 fn get_output_vec(size: usize) -> Vec<u8> {
-    repeat(0).take((size + 7) / 8).collect()
+    vec![0; size.div_ceil(8)]
 }
 
 #[cfg(test)]
 mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
     use super::{
-        decode_to_base64_vec, encode_to_base64, encrypt_with_md5, encrypt_with_sha1,
-        generate_command_signature, generate_signed_nonce, get_random_string,
+        decode_to_base64_vec, decrypt_with_aes_gcm, decrypt_with_rc4, derive_key, encode_to_base64,
+        encrypt_with_aes_gcm, encrypt_with_md5, encrypt_with_rc4, encrypt_with_sha1,
+        generate_command_signature, generate_encrypted_signature, generate_nonce,
+        generate_signed_nonce, get_random_string,
     };
 
     #[test]
@@ -108,6 +205,14 @@ mod test {
         println!("{}", random);
     }
 
+    #[test]
+    fn test_nonce() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let nonce = decode_to_base64_vec(&generate_nonce(now));
+        assert_eq!(12, nonce.len());
+        assert_eq!((1_700_000_000u32 / 60).to_be_bytes(), nonce[8..]);
+    }
+
     #[test]
     fn test_signed_nonce() {
         let result = generate_signed_nonce("test", "1234");
@@ -119,4 +224,47 @@ mod test {
         let result = generate_command_signature("test", "test", "test", "test");
         assert_eq!("IOSP119Hekgo9THjxG7OvJDpaiRwOMVsL05krsJqG/4=", result)
     }
+
+    #[test]
+    fn test_rc4() {
+        let encrypted = encrypt_with_rc4("dGVzdGtleQ==", br#"{"getVirtualModel":false}"#);
+        assert_eq!("y5LhIiuaXRbX2tzzJLJfQrPA9Ftobi/T4g==", encrypted);
+        assert_eq!(
+            br#"{"getVirtualModel":false}"#.to_vec(),
+            decrypt_with_rc4("dGVzdGtleQ==", &encrypted).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_generate_encrypted_signature() {
+        let result = generate_encrypted_signature(
+            "post",
+            "/home/device_list",
+            "dGVzdGtleQ==",
+            &[("data", "abc"), ("rc4_hash__", "def")],
+        );
+        assert_eq!("O7FJW0rs0tv4EOptXX4dk6BfMYQ=", result)
+    }
+
+    #[test]
+    fn test_derive_key() {
+        // RFC 7914 PBKDF2-HMAC-SHA256 测试向量的前32字节
+        let key = derive_key("passwd", b"salt", 1);
+        assert_eq!(
+            "VawEblbjCJ/sFpHCJUS2BflBhSFt3gRl5oudV8INrLw=",
+            encode_to_base64(&key)
+        );
+    }
+
+    #[test]
+    fn test_aes_gcm() {
+        let key = derive_key("test", b"salt", 1);
+        let encrypted = encrypt_with_aes_gcm(&key, b"test");
+        assert_eq!(
+            b"test".to_vec(),
+            decrypt_with_aes_gcm(&key, &encrypted).unwrap()
+        );
+        let wrong_key = derive_key("wrong", b"salt", 1);
+        assert!(decrypt_with_aes_gcm(&wrong_key, &encrypted).is_none());
+    }
 }
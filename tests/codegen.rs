@@ -0,0 +1,48 @@
+//! 编译`codegen`生成的代码，确认生成结果能通过类型检查
+
+use mikit_rust::kit::MiKit;
+use mikit_rust::models::{ActionResult, MiotValue};
+
+include!("fixtures/devices.rs");
+
+#[allow(dead_code)]
+async fn use_generated(kit: &MiKit) -> anyhow::Result<ActionResult> {
+    let light = yeelink_light_color1::YeelinkLightColor1::new(kit, "did");
+    light.set_on(!light.get_on().await?).await?;
+    light.set_brightness(light.get_brightness().await?).await?;
+    light.set_mode(yeelink_light_color1::Mode::Night).await?;
+    let _: u32 = light.get_color_temperature().await?;
+    light.toggle().await?;
+
+    let fan = mikit_fan_edge::MikitFanEdge::new(kit, "did");
+    fan.set_type(&fan.get_type().await?).await?;
+    fan.set_raw(fan.get_raw().await?).await?;
+    fan.set_speed(1.5).await?;
+    fan.set_fan_on_2(fan.get_fan_on_2().await?).await?;
+    fan.get_type_action(fan.get_fan_on().await?).await?;
+    fan.new_action().await?;
+    fan.fan_set(
+        fan.get_mode().await?,
+        "type",
+        mikit_fan_edge::Mode::Auto,
+        MiotValue::from("raw"),
+        0.5,
+    )
+    .await?;
+    fan.indicator_light_set(fan.get_indicator_light_on().await?)
+        .await
+}
+
+#[test]
+fn test_generated_code() {
+    assert_eq!(
+        "yeelink.light.color1",
+        yeelink_light_color1::YeelinkLightColor1::MODEL
+    );
+    assert_eq!(-1, mikit_fan_edge::Mode::Auto.value());
+    assert_eq!(
+        Some(mikit_fan_edge::Mode::Value1Hour),
+        mikit_fan_edge::Mode::from_value(2)
+    );
+    assert_eq!(None, yeelink_light_color1::Mode::from_value(2));
+}
@@ -0,0 +1,317 @@
+/// Edge Case Fan (mikit.fan.edge)
+#[allow(dead_code, unused_imports)]
+pub mod mikit_fan_edge {
+    use ::mikit_rust::kit::{MiKit, PROPERTY_FAILED_CODE};
+    use ::mikit_rust::models::{ActionResult, MikitError, MiotValue};
+
+    /// Mode with a second line
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Mode {
+        Auto,
+        Value0,
+        Value1,
+        Value1Hour,
+    }
+
+    impl Mode {
+        pub fn value(self) -> i64 {
+            match self {
+                Mode::Auto => -1,
+                Mode::Value0 => 0,
+                Mode::Value1 => 1,
+                Mode::Value1Hour => 2,
+            }
+        }
+
+        pub fn from_value(value: i64) -> Option<Self> {
+            match value {
+                -1 => Some(Mode::Auto),
+                0 => Some(Mode::Value0),
+                1 => Some(Mode::Value1),
+                2 => Some(Mode::Value1Hour),
+                _ => None,
+            }
+        }
+    }
+
+    pub struct MikitFanEdge<'a> {
+        kit: &'a MiKit,
+        did: String,
+    }
+
+    impl<'a> MikitFanEdge<'a> {
+        pub const MODEL: &'static str = "mikit.fan.edge";
+
+        pub fn new(kit: &'a MiKit, did: &str) -> Self {
+            Self {
+                kit,
+                did: did.to_string(),
+            }
+        }
+
+        /// Switch Status
+        pub async fn get_fan_on(&self) -> ::anyhow::Result<bool> {
+            let value = self
+                .kit
+                .get_property_by_iid(&self.did, 2, 1)
+                .await?;
+            value
+                .as_bool()
+                .ok_or_else(|| MikitError::Property("fan.on".to_string(), PROPERTY_FAILED_CODE).into())
+        }
+
+        /// Switch Status
+        pub async fn set_fan_on(&self, value: bool) -> ::anyhow::Result<()> {
+            self.kit
+                .set_property_by_iid(&self.did, 2, 1, MiotValue::from(value))
+                .await
+        }
+
+        /// Mode with a second line
+        pub async fn get_mode(&self) -> ::anyhow::Result<Mode> {
+            let value = self
+                .kit
+                .get_property_by_iid(&self.did, 2, 2)
+                .await?;
+            value
+                .as_i64()
+                .and_then(Mode::from_value)
+                .ok_or_else(|| MikitError::Property("fan.mode".to_string(), PROPERTY_FAILED_CODE).into())
+        }
+
+        /// Mode with a second line
+        pub async fn set_mode(&self, value: Mode) -> ::anyhow::Result<()> {
+            self.kit
+                .set_property_by_iid(&self.did, 2, 2, MiotValue::from(value.value()))
+                .await
+        }
+
+        /// Type
+        pub async fn get_type(&self) -> ::anyhow::Result<String> {
+            let value = self
+                .kit
+                .get_property_by_iid(&self.did, 2, 3)
+                .await?;
+            value
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| MikitError::Property("fan.type".to_string(), PROPERTY_FAILED_CODE).into())
+        }
+
+        /// Type
+        pub async fn set_type(&self, value: &str) -> ::anyhow::Result<()> {
+            self.kit
+                .set_property_by_iid(&self.did, 2, 3, MiotValue::from(value))
+                .await
+        }
+
+        /// Raw */ data
+        pub async fn get_raw(&self) -> ::anyhow::Result<MiotValue> {
+            let value = self
+                .kit
+                .get_property_by_iid(&self.did, 2, 4)
+                .await?;
+            Ok(value)
+        }
+
+        /// Raw */ data
+        pub async fn set_raw(&self, value: MiotValue) -> ::anyhow::Result<()> {
+            self.kit
+                .set_property_by_iid(&self.did, 2, 4, value)
+                .await
+        }
+
+        /// Speed [0, 10.5], step 0.5 (rpm)
+        pub async fn set_speed(&self, value: f64) -> ::anyhow::Result<()> {
+            self.kit
+                .set_property_by_iid(&self.did, 2, 5, MiotValue::from(value))
+                .await
+        }
+
+        /// New Action
+        pub async fn new_action(&self) -> ::anyhow::Result<ActionResult> {
+            self.kit
+                .call_action(&self.did, 2, 1, &[])
+                .await
+        }
+
+        /// Get Type
+        pub async fn get_type_action(&self, on: bool) -> ::anyhow::Result<ActionResult> {
+            self.kit
+                .call_action(&self.did, 2, 2, &[MiotValue::from(on)])
+                .await
+        }
+
+        /// Set
+        pub async fn fan_set(&self, mode: Mode, type_: &str, mode_2: Mode, raw: MiotValue, speed: f64) -> ::anyhow::Result<ActionResult> {
+            self.kit
+                .call_action(&self.did, 2, 3, &[MiotValue::from(mode.value()), MiotValue::from(type_), MiotValue::from(mode_2.value()), raw, MiotValue::from(speed)])
+                .await
+        }
+
+        /// Switch Status
+        pub async fn get_indicator_light_on(&self) -> ::anyhow::Result<bool> {
+            let value = self
+                .kit
+                .get_property_by_iid(&self.did, 3, 1)
+                .await?;
+            value
+                .as_bool()
+                .ok_or_else(|| MikitError::Property("indicator-light.on".to_string(), PROPERTY_FAILED_CODE).into())
+        }
+
+        /// Switch Status
+        pub async fn set_indicator_light_on(&self, value: bool) -> ::anyhow::Result<()> {
+            self.kit
+                .set_property_by_iid(&self.did, 3, 1, MiotValue::from(value))
+                .await
+        }
+
+        /// Fan On Delay [0, 60], step 1 (minutes)
+        pub async fn get_fan_on_2(&self) -> ::anyhow::Result<u8> {
+            let value = self
+                .kit
+                .get_property_by_iid(&self.did, 3, 2)
+                .await?;
+            value
+                .as_i64()
+                .and_then(|value| u8::try_from(value).ok())
+                .ok_or_else(|| MikitError::Property("indicator-light.fan-on".to_string(), PROPERTY_FAILED_CODE).into())
+        }
+
+        /// Fan On Delay [0, 60], step 1 (minutes)
+        pub async fn set_fan_on_2(&self, value: u8) -> ::anyhow::Result<()> {
+            self.kit
+                .set_property_by_iid(&self.did, 3, 2, MiotValue::from(value))
+                .await
+        }
+
+        /// Set
+        pub async fn indicator_light_set(&self, on: bool) -> ::anyhow::Result<ActionResult> {
+            self.kit
+                .call_action(&self.did, 3, 1, &[MiotValue::from(on)])
+                .await
+        }
+    }
+}
+/// Light (yeelink.light.color1)
+#[allow(dead_code, unused_imports)]
+pub mod yeelink_light_color1 {
+    use ::mikit_rust::kit::{MiKit, PROPERTY_FAILED_CODE};
+    use ::mikit_rust::models::{ActionResult, MikitError, MiotValue};
+
+    /// Mode
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Mode {
+        Day,
+        Night,
+    }
+
+    impl Mode {
+        pub fn value(self) -> i64 {
+            match self {
+                Mode::Day => 0,
+                Mode::Night => 1,
+            }
+        }
+
+        pub fn from_value(value: i64) -> Option<Self> {
+            match value {
+                0 => Some(Mode::Day),
+                1 => Some(Mode::Night),
+                _ => None,
+            }
+        }
+    }
+
+    pub struct YeelinkLightColor1<'a> {
+        kit: &'a MiKit,
+        did: String,
+    }
+
+    impl<'a> YeelinkLightColor1<'a> {
+        pub const MODEL: &'static str = "yeelink.light.color1";
+
+        pub fn new(kit: &'a MiKit, did: &str) -> Self {
+            Self {
+                kit,
+                did: did.to_string(),
+            }
+        }
+
+        /// Switch Status
+        pub async fn get_on(&self) -> ::anyhow::Result<bool> {
+            let value = self
+                .kit
+                .get_property_by_iid(&self.did, 2, 1)
+                .await?;
+            value
+                .as_bool()
+                .ok_or_else(|| MikitError::Property("light.on".to_string(), PROPERTY_FAILED_CODE).into())
+        }
+
+        /// Switch Status
+        pub async fn set_on(&self, value: bool) -> ::anyhow::Result<()> {
+            self.kit
+                .set_property_by_iid(&self.did, 2, 1, MiotValue::from(value))
+                .await
+        }
+
+        /// Brightness [1, 100], step 1 (percentage)
+        pub async fn get_brightness(&self) -> ::anyhow::Result<u8> {
+            let value = self
+                .kit
+                .get_property_by_iid(&self.did, 2, 2)
+                .await?;
+            value
+                .as_i64()
+                .and_then(|value| u8::try_from(value).ok())
+                .ok_or_else(|| MikitError::Property("light.brightness".to_string(), PROPERTY_FAILED_CODE).into())
+        }
+
+        /// Brightness [1, 100], step 1 (percentage)
+        pub async fn set_brightness(&self, value: u8) -> ::anyhow::Result<()> {
+            self.kit
+                .set_property_by_iid(&self.did, 2, 2, MiotValue::from(value))
+                .await
+        }
+
+        /// Mode
+        pub async fn get_mode(&self) -> ::anyhow::Result<Mode> {
+            let value = self
+                .kit
+                .get_property_by_iid(&self.did, 2, 3)
+                .await?;
+            value
+                .as_i64()
+                .and_then(Mode::from_value)
+                .ok_or_else(|| MikitError::Property("light.mode".to_string(), PROPERTY_FAILED_CODE).into())
+        }
+
+        /// Mode
+        pub async fn set_mode(&self, value: Mode) -> ::anyhow::Result<()> {
+            self.kit
+                .set_property_by_iid(&self.did, 2, 3, MiotValue::from(value.value()))
+                .await
+        }
+
+        /// Color Temperature [1700, 6500], step 1 (kelvin)
+        pub async fn get_color_temperature(&self) -> ::anyhow::Result<u32> {
+            let value = self
+                .kit
+                .get_property_by_iid(&self.did, 2, 4)
+                .await?;
+            value
+                .as_i64()
+                .and_then(|value| u32::try_from(value).ok())
+                .ok_or_else(|| MikitError::Property("light.color-temperature".to_string(), PROPERTY_FAILED_CODE).into())
+        }
+
+        /// Toggle
+        pub async fn toggle(&self) -> ::anyhow::Result<ActionResult> {
+            self.kit
+                .call_action(&self.did, 2, 1, &[])
+                .await
+        }
+    }
+}